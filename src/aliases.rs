@@ -0,0 +1,189 @@
+use crate::names::normalise_name;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The alias file as written to disk
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AliasFile {
+    /// Raw author strings, or several joined with "; ", and the name they stand for
+    #[serde(default)]
+    authors: BTreeMap<String, String>,
+}
+
+/// Canonical names for the author strings found in files, remembered between runs so that
+/// "Pratchett, Terry" only has to be settled once
+#[derive(Debug, Default)]
+pub struct AuthorAliases {
+    /// Where changes are saved, nowhere if not set
+    path: Option<PathBuf>,
+    authors: RefCell<BTreeMap<String, String>>,
+}
+
+/// Collapse whitespace so differently spaced copies of a name share an alias
+fn clean(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The key for a set of raw author strings, in any order
+fn combination_key(raw: &[String]) -> String {
+    let mut names: Vec<String> = raw.iter().map(|name| clean(name)).collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.dedup_by_key(|name| name.to_lowercase());
+    names.join("; ")
+}
+
+/// Whether `chosen` is just another way of writing `raw`, such as "Terry Pratchett" for
+/// "Pratchett, Terry"
+fn is_variant(raw: &str, chosen: &str) -> bool {
+    let chosen = clean(chosen).to_lowercase();
+    let flipped = normalise_name(raw);
+    [clean(raw), flipped]
+        .iter()
+        .chain(raw.split(',').map(clean).collect::<Vec<_>>().iter())
+        .any(|variant| variant.to_lowercase() == chosen)
+}
+
+impl AuthorAliases {
+    /// Load the aliases at `path`, starting with none if the file doesn't exist yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => AliasFile::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(AuthorAliases {
+            path: Some(path.to_path_buf()),
+            authors: RefCell::new(file.authors),
+        })
+    }
+
+    /// Keep the authors picked from now on for this run only, without saving them
+    pub fn read_only(self) -> Self {
+        AuthorAliases { path: None, ..self }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let key = key.to_lowercase();
+        self.authors
+            .borrow()
+            .iter()
+            .find(|(raw, _)| raw.to_lowercase() == key)
+            .map(|(_, canonical)| canonical.clone())
+    }
+
+    /// The canonical author for these raw strings: the answer given for exactly these before,
+    /// or the name they are all aliases of
+    pub fn canonical(&self, raw: &[String]) -> Option<String> {
+        if raw.is_empty() {
+            return None;
+        }
+        if let Some(canonical) = self.get(&combination_key(raw)) {
+            return Some(canonical);
+        }
+        let mut names = raw.iter().map(|name| self.get(&clean(name)));
+        let first = names.next()??;
+        names
+            .all(|name| name.as_ref() == Some(&first))
+            .then_some(first)
+    }
+
+    /// Remember that `chosen` was picked for these raw strings, and save straight away
+    pub fn remember(&self, raw: &[String], chosen: &str) {
+        let chosen = clean(chosen);
+        if raw.is_empty() || chosen.is_empty() {
+            return;
+        }
+        {
+            let mut authors = self.authors.borrow_mut();
+            authors.insert(combination_key(raw), chosen.clone());
+            // Only the strings that are another way of writing the chosen name become its
+            // aliases, so a co-author isn't mistaken for them in their own books
+            for name in raw.iter().filter(|name| is_variant(name, &chosen)) {
+                authors.insert(clean(name), chosen.clone());
+            }
+        }
+        if let Err(e) = self.save() {
+            eprintln!("Warning: Failed to save author aliases: {e}");
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = AliasFile {
+            authors: self.authors.borrow().clone(),
+        };
+        let serialized =
+            toml::to_string(&file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, serialized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_remember_and_reload() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_aliases_test_{}.toml",
+            rand::random::<u64>()
+        ));
+        let aliases = AuthorAliases::load(&path)?;
+        aliases.remember(
+            &names(&["Pratchett, Terry", "Terry  Pratchett"]),
+            "Terry Pratchett",
+        );
+        aliases.remember(
+            &names(&["Terry Pratchett", "Neil Gaiman"]),
+            "Terry Pratchett",
+        );
+
+        let aliases = AuthorAliases::load(&path)?;
+        // Exactly the same strings, in any order
+        assert_eq!(
+            aliases
+                .canonical(&names(&["Neil Gaiman", "Terry Pratchett"]))
+                .as_deref(),
+            Some("Terry Pratchett")
+        );
+        // Strings that are all aliases of one name
+        assert_eq!(
+            aliases.canonical(&names(&["pratchett, terry"])).as_deref(),
+            Some("Terry Pratchett")
+        );
+        // Neil Gaiman was a co-author, not another name for Terry Pratchett
+        assert_eq!(aliases.canonical(&names(&["Neil Gaiman"])), None);
+        assert_eq!(
+            aliases.canonical(&names(&["Terry Pratchett", "Stephen Baxter"])),
+            None
+        );
+
+        // Nothing is written without a path
+        let read_only = AuthorAliases::load(&path)?.read_only();
+        read_only.remember(&names(&["Neil Gaiman"]), "Neil Gaiman");
+        assert_eq!(
+            read_only.canonical(&names(&["Neil Gaiman"])).as_deref(),
+            Some("Neil Gaiman")
+        );
+        assert_eq!(
+            AuthorAliases::load(&path)?.canonical(&names(&["Neil Gaiman"])),
+            None
+        );
+
+        fs::remove_file(&path)
+    }
+}
@@ -0,0 +1,177 @@
+use crate::fileops::files_in;
+use crate::parsers::file_extension;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What to do when a file's destination already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Ask each time
+    #[default]
+    Ask,
+    /// Leave the file where it is
+    Skip,
+    /// Replace the file at the destination
+    Overwrite,
+    /// Add a number to the name, as in "Title (2).epub"
+    Rename,
+    /// Keep whichever file is larger
+    KeepLarger,
+    /// Keep whichever file was modified most recently
+    KeepNewer,
+}
+
+/// How a collision was settled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Leave the file where it is, with the reason why
+    Skip(String),
+    /// Replace the file at the destination
+    Overwrite,
+    /// Use this destination instead
+    Rename(PathBuf),
+}
+
+/// Settle a collision between `file_path` and the existing `destination` without asking.
+///
+/// `taken` says whether a renamed destination is already in use. `Ask` has to be turned into
+/// one of the other policies first, so here it skips.
+pub fn resolve(
+    policy: CollisionPolicy,
+    file_path: &Path,
+    destination: &Path,
+    taken: &dyn Fn(&Path) -> bool,
+) -> Resolution {
+    match policy {
+        CollisionPolicy::Ask | CollisionPolicy::Skip => {
+            Resolution::Skip(String::from("the destination already exists"))
+        }
+        CollisionPolicy::Overwrite => Resolution::Overwrite,
+        CollisionPolicy::Rename => Resolution::Rename(numbered_path(destination, taken)),
+        CollisionPolicy::KeepLarger => {
+            if size(file_path) > size(destination) {
+                Resolution::Overwrite
+            } else {
+                Resolution::Skip(String::from(
+                    "the file at the destination is as large or larger",
+                ))
+            }
+        }
+        CollisionPolicy::KeepNewer => {
+            if modified(file_path) > modified(destination) {
+                Resolution::Overwrite
+            } else {
+                Resolution::Skip(String::from(
+                    "the file at the destination is as new or newer",
+                ))
+            }
+        }
+    }
+}
+
+/// The first of "Title (2).epub", "Title (3).epub" and so on that isn't taken
+pub fn numbered_path(path: &Path, taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // An audiobook folder has no extension to keep, and "fb2.zip" is kept whole
+    let extension = if path.is_dir() {
+        None
+    } else {
+        file_extension(path)
+    };
+    // The extension is looked up in lower case, but kept as it was written
+    let (stem, extension) = match extension {
+        Some(extension) if name.len() > extension.len() + 1 => {
+            name.split_at(name.len() - extension.len() - 1)
+        }
+        _ => (name.as_ref(), ""),
+    };
+    (2..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){extension}")))
+        .find(|candidate| !candidate.exists() && !taken(candidate))
+        .expect("there is always a free number")
+}
+
+/// Size of a file, or of everything in a folder
+fn size(path: &Path) -> u64 {
+    if path.is_dir() {
+        files_in(path)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|file| fs::metadata(path.join(file)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    } else {
+        fs::metadata(path).map_or(0, |metadata| metadata.len())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_collisions() -> std::io::Result<()> {
+        let root = std::env::temp_dir().join(format!(
+            "ebook_organiser_collision_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&root)?;
+        let existing = root.join("Title.epub");
+        fs::write(&existing, b"existing")?;
+        fs::write(root.join("Title (2).epub"), b"taken")?;
+        let larger = root.join("larger.epub");
+        fs::write(&larger, b"a larger file")?;
+        let smaller = root.join("smaller.epub");
+        fs::write(&smaller, b"small")?;
+        let nothing_taken = |_: &Path| false;
+
+        assert_eq!(
+            resolve(CollisionPolicy::Rename, &larger, &existing, &nothing_taken),
+            Resolution::Rename(root.join("Title (3).epub"))
+        );
+        assert_eq!(
+            resolve(CollisionPolicy::Rename, &larger, &existing, &|p: &Path| {
+                p.ends_with("Title (3).epub")
+            }),
+            Resolution::Rename(root.join("Title (4).epub"))
+        );
+        assert_eq!(
+            numbered_path(&root.join("Title.fb2.zip"), &nothing_taken),
+            root.join("Title (2).fb2.zip")
+        );
+        assert_eq!(
+            numbered_path(&root.join("Title.FB2.ZIP"), &nothing_taken),
+            root.join("Title (2).FB2.ZIP")
+        );
+        assert_eq!(
+            resolve(
+                CollisionPolicy::KeepLarger,
+                &larger,
+                &existing,
+                &nothing_taken
+            ),
+            Resolution::Overwrite
+        );
+        assert!(matches!(
+            resolve(
+                CollisionPolicy::KeepLarger,
+                &smaller,
+                &existing,
+                &nothing_taken
+            ),
+            Resolution::Skip(_)
+        ));
+
+        fs::remove_dir_all(&root)
+    }
+}
@@ -1,15 +1,49 @@
+use crate::collision::CollisionPolicy;
+use crate::duplicates::DuplicatePolicy;
+use crate::routing::Rule;
+use crate::sanitise::PathStyle;
+use crate::sorter::{ApprovePolicy, parse_template};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
 pub struct Config {
     pub library_path: String,
     pub audiobook_library_path: String,
+    pub comic_library_path: String,
     pub input_path: String,
     pub format_template: String,
+    /// How strictly metadata is cleaned before it is used in a path: posix, windows or ascii
+    pub path_style: PathStyle,
+    /// Longest file or folder name to create, in bytes
+    pub max_component_length: usize,
     pub copy: bool,
+    /// What to do with a file that is already in the library: ask, skip, replace or keep_both
+    pub duplicates: DuplicatePolicy,
+    /// What to do when the destination already exists: ask, skip, overwrite, rename,
+    /// keep_larger or keep_newer
+    pub collisions: CollisionPolicy,
+    /// When to move or copy without asking first: ask, always, when_parsed or per_directory
+    pub approve: ApprovePolicy,
+    /// Where every move and copy is recorded so it can be undone
+    pub journal_path: String,
+    /// Where non-interactive runs list the files that need an answer before they can be sorted
+    pub review_queue_path: String,
+    /// Template for the audiobook library, `format_template` is used if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audiobook_format_template: Option<String>,
+    /// Template for the comic library, `format_template` is used if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comic_format_template: Option<String>,
+    /// Templates for particular extensions, which win over the library's template
+    pub templates: BTreeMap<String, String>,
+    /// Rules sending matching files to other libraries, checked in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -17,19 +51,112 @@ impl Default for Config {
             library_path: String::from("/tmp/library"),
             input_path: String::from("/tmp/input"),
             format_template: String::from("{author}/{title}.{ext}"),
+            path_style: PathStyle::default(),
+            max_component_length: 255,
             audiobook_library_path: String::from("/tmp/audiobook_library"),
+            comic_library_path: String::from("/tmp/comic_library"),
             copy: false,
+            duplicates: DuplicatePolicy::default(),
+            collisions: CollisionPolicy::default(),
+            approve: ApprovePolicy::default(),
+            journal_path: dirs::data_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("ebook-organiser")
+                .join("journal.jsonl")
+                .to_string_lossy()
+                .into_owned(),
+            review_queue_path: dirs::data_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join("ebook-organiser")
+                .join("review.json")
+                .to_string_lossy()
+                .into_owned(),
+            audiobook_format_template: None,
+            comic_format_template: None,
+            templates: BTreeMap::new(),
+            rules: Vec::new(),
         }
     }
 }
 impl Config {
+    /// Load the config, failing if any of its templates or rules are invalid
     pub fn load(path: &Path) -> io::Result<Self> {
         let contents = fs::read_to_string(path)?;
-        let config: Config =
+        let mut config: Config =
             toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // Extensions are matched in lowercase, so `PDF = ...` applies to "book.pdf" too
+        config.templates = config
+            .templates
+            .into_iter()
+            .map(|(extension, template)| (extension.to_lowercase(), template))
+            .collect();
+        let problems = config.check();
+        if !problems.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                problems.join("\n"),
+            ));
+        }
         Ok(config)
     }
 
+    /// Every template setting, by name
+    fn template_settings(&self) -> Vec<(String, &str)> {
+        let mut settings = vec![(
+            String::from("format_template"),
+            self.format_template.as_str(),
+        )];
+        if let Some(template) = &self.audiobook_format_template {
+            settings.push((String::from("audiobook_format_template"), template));
+        }
+        if let Some(template) = &self.comic_format_template {
+            settings.push((String::from("comic_format_template"), template));
+        }
+        for (extension, template) in &self.templates {
+            settings.push((format!("templates.{extension}"), template));
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(template) = &rule.template {
+                settings.push((format!("rules[{i}].template"), template));
+            }
+        }
+        settings
+    }
+
+    /// Describe each problem with the config, pointing at the offending position of templates
+    pub fn check(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .template_settings()
+            .into_iter()
+            .filter_map(|(setting, template)| {
+                let error = parse_template(template).err()?;
+                let column = template[..error.position].chars().count();
+                Some(format!(
+                    "{setting}: {error}\n    {template}\n    {}^",
+                    " ".repeat(column)
+                ))
+            })
+            .collect();
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.library.trim().is_empty() {
+                problems.push(format!("rules[{i}]: library is not set"));
+            }
+        }
+        problems
+    }
+
+    pub fn audiobook_template(&self) -> &str {
+        self.audiobook_format_template
+            .as_deref()
+            .unwrap_or(&self.format_template)
+    }
+
+    pub fn comic_template(&self) -> &str {
+        self.comic_format_template
+            .as_deref()
+            .unwrap_or(&self.format_template)
+    }
+
     pub fn save(&self, path: &Path) -> io::Result<()> {
         let serialized = toml::to_string_pretty(self)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
@@ -71,6 +198,7 @@ mod tests {
         let toml_content = r#"
         library_path = "/tmp/library"
         audiobook_library_path = "/tmp/audiobook_library"
+        comic_library_path = "/tmp/comic_library"
         input_path = "/tmp/input"
         format_template = "{author}/{title}.{ext}"
         copy = false
@@ -112,6 +240,54 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_template_fallbacks() -> io::Result<()> {
+        let temp_path = get_temp_file_path();
+        let toml_content = r#"
+        format_template = "{author}/{title}.{ext}"
+        audiobook_format_template = "{author}/{series?}{series}/{/series}{title}.{ext}"
+
+        [templates]
+        cbz = "{series}/{title}.{ext}"
+        PDF = "Papers/{title}.{ext}"
+        "#;
+        fs::write(&temp_path, toml_content)?;
+        let config = Config::load(&temp_path)?;
+        fs::remove_file(&temp_path)?;
+
+        assert_eq!(
+            config.audiobook_template(),
+            "{author}/{series?}{series}/{/series}{title}.{ext}"
+        );
+        assert_eq!(config.comic_template(), "{author}/{title}.{ext}");
+        assert_eq!(
+            config.templates.get("cbz").map(String::as_str),
+            Some("{series}/{title}.{ext}")
+        );
+        assert_eq!(
+            config.templates.get("pdf").map(String::as_str),
+            Some("Papers/{title}.{ext}")
+        );
+        assert!(!config.templates.contains_key("PDF"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_template() -> io::Result<()> {
+        let temp_path = get_temp_file_path();
+        fs::write(&temp_path, "format_template = \"{autor}/{title}.{ext}\"")?;
+        let error = Config::load(&temp_path).unwrap_err();
+        fs::remove_file(&temp_path)?;
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            error
+                .to_string()
+                .starts_with("format_template: unknown variable '{autor}'")
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_default_config() {
         let default_config = Config::default();
@@ -123,5 +299,6 @@ mod tests {
             default_config.audiobook_library_path,
             "/tmp/audiobook_library"
         );
+        assert_eq!(default_config.comic_library_path, "/tmp/comic_library");
     }
 }
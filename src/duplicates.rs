@@ -0,0 +1,125 @@
+use crate::hash::hash_file;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What to do with a file whose contents are already somewhere in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Ask each time
+    #[default]
+    Ask,
+    /// Leave the incoming file where it is
+    Skip,
+    /// Sort the incoming file and remove the copy already in the library
+    Replace,
+    /// Sort the incoming file as normal
+    KeepBoth,
+}
+
+/// The files in a library by size, so only files of the same size ever need hashing
+#[derive(Debug, Default)]
+pub struct LibraryIndex {
+    by_size: HashMap<u64, Vec<PathBuf>>,
+    hashes: HashMap<PathBuf, String>,
+}
+
+impl LibraryIndex {
+    /// Index every file under `root`, which may not exist yet
+    pub fn build(root: &Path) -> Self {
+        let mut index = LibraryIndex::default();
+        let mut folders = vec![root.to_path_buf()];
+        while let Some(folder) = folders.pop() {
+            let Ok(entries) = fs::read_dir(&folder) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    folders.push(path);
+                } else {
+                    index.insert(&path);
+                }
+            }
+        }
+        index
+    }
+
+    pub fn insert(&mut self, path: &Path) {
+        if let Ok(metadata) = fs::metadata(path) {
+            let paths = self.by_size.entry(metadata.len()).or_default();
+            if !paths.iter().any(|p| p == path) {
+                paths.push(path.to_path_buf());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        for paths in self.by_size.values_mut() {
+            paths.retain(|p| p != path);
+        }
+        self.hashes.remove(path);
+    }
+
+    fn hash(&mut self, path: &Path) -> Option<String> {
+        if let Some(hash) = self.hashes.get(path) {
+            return Some(hash.clone());
+        }
+        let hash = hash_file(path).ok()?;
+        self.hashes.insert(path.to_path_buf(), hash.clone());
+        Some(hash)
+    }
+
+    /// Find a file in the library with the same contents, ignoring the paths in `except`
+    pub fn find_duplicate(&mut self, file_path: &Path, except: &[&Path]) -> Option<PathBuf> {
+        let size = fs::metadata(file_path).ok().filter(|m| m.is_file())?.len();
+        let candidates: Vec<PathBuf> = self
+            .by_size
+            .get(&size)?
+            .iter()
+            .filter(|p| !except.contains(&p.as_path()))
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let hash = hash_file(file_path).ok()?;
+        candidates
+            .into_iter()
+            .find(|candidate| self.hash(candidate).as_ref() == Some(&hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicate() -> std::io::Result<()> {
+        let root = std::env::temp_dir().join(format!(
+            "ebook_organiser_duplicates_test_{}",
+            rand::random::<u64>()
+        ));
+        let library = root.join("library");
+        fs::create_dir_all(library.join("Author"))?;
+        let existing = library.join("Author").join("Book.epub");
+        fs::write(&existing, b"same contents")?;
+        // Same size, different contents
+        fs::write(library.join("Other.epub"), b"diff contents")?;
+        let incoming = root.join("incoming.epub");
+        fs::write(&incoming, b"same contents")?;
+        let different = root.join("different.epub");
+        fs::write(&different, b"other")?;
+
+        let mut index = LibraryIndex::build(&library);
+        assert_eq!(index.find_duplicate(&incoming, &[]), Some(existing.clone()));
+        assert_eq!(index.find_duplicate(&incoming, &[&existing]), None);
+        assert_eq!(index.find_duplicate(&different, &[]), None);
+        index.remove(&existing);
+        assert_eq!(index.find_duplicate(&incoming, &[]), None);
+
+        fs::remove_dir_all(&root)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::plan::Operation;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// List every file under a folder, relative to it
+pub fn files_in(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        let name = PathBuf::from(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            files.extend(files_in(&path)?.into_iter().map(|file| name.join(file)));
+        } else {
+            files.push(name);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The folders that have to be created to put something at `path`, deepest first
+pub fn missing_folders(path: &Path) -> Vec<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|folder| !folder.as_os_str().is_empty() && !folder.exists())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// Copy a file, or a whole folder and everything in it
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        for file in files_in(from)? {
+            if let Some(parent) = to.join(&file).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(from.join(&file), to.join(&file))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+/// Remove a file, or a whole folder and everything in it
+pub fn remove_all(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Move or copy a file (or an audiobook folder) to its new location, creating any missing parent folders.
+///
+/// Returns true if the file ended up at `new_file_path`.
+pub fn transfer(operation: Operation, file_path: &Path, new_file_path: &Path) -> bool {
+    // Need to make folder path if it doesn't exist
+    if let Some(parent) = new_file_path.parent()
+        && !parent.exists()
+        && let Err(e) = std::fs::create_dir_all(parent)
+    {
+        eprintln!("Failed to create directory {}: {}", parent.display(), e);
+        return false;
+    }
+
+    match operation {
+        Operation::Copy => {
+            // Copy mode - copy file and leave original intact
+            if copy_all(file_path, new_file_path).is_ok() {
+                println!(
+                    "Copied {} to {}",
+                    file_path.display(),
+                    new_file_path.display()
+                );
+                true
+            } else {
+                eprintln!("Failed to copy file {}", file_path.display());
+                false
+            }
+        }
+        Operation::Move => {
+            // Move/rename mode (default behavior)
+            if let Err(e) = std::fs::rename(file_path, new_file_path) {
+                // If rename fails, try copy + delete as fallback
+                if copy_all(file_path, new_file_path).is_ok() {
+                    if let Err(e) = remove_all(file_path) {
+                        eprintln!("Failed to remove original file: {e}");
+                    }
+                    println!(
+                        "Moved (via copy) {} to {}",
+                        file_path.display(),
+                        new_file_path.display()
+                    );
+                    true
+                } else {
+                    eprintln!("Failed to copy file: {e}");
+                    false
+                }
+            } else {
+                println!(
+                    "Moved {} to {}",
+                    file_path.display(),
+                    new_file_path.display()
+                );
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Hash the contents of a file, returning the SHA-256 digest as lowercase hex
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Check whether two files have the same contents, only hashing them if their sizes match
+pub fn same_contents(a: &Path, b: &Path) -> bool {
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) if a_meta.is_file() && a_meta.len() == b_meta.len() => {
+            matches!((hash_file(a), hash_file(b)), (Ok(a_hash), Ok(b_hash)) if a_hash == b_hash)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,392 @@
+use crate::collision::numbered_path;
+use crate::fileops::{files_in, missing_folders, transfer};
+use crate::hash::hash_file;
+use crate::plan::Operation;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single successful move or copy, as written to the journal
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalRecord {
+    pub run_id: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub operation: Operation,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub size: u64,
+    pub hash: String,
+    /// Folders the operation created, which undo removes again once they are empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub created_folders: Vec<PathBuf>,
+}
+
+/// Append-only log of the file operations made by each run, one JSON record per line
+pub struct Journal {
+    path: PathBuf,
+    run_id: String,
+    /// The number of records written by this run
+    recorded: Cell<usize>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Journal {
+    /// Open the journal at `path` for a new run
+    pub fn new(path: &Path) -> Self {
+        Journal {
+            path: path.to_path_buf(),
+            run_id: format!("{}-{}", now(), std::process::id()),
+            recorded: Cell::new(0),
+        }
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Whether this run has recorded anything that undo could reverse
+    pub fn has_records(&self) -> bool {
+        self.recorded.get() > 0
+    }
+
+    /// Record a completed operation, warning (but carrying on) if the journal can't be written.
+    /// `created_folders` are the folders that were missing before it, from `missing_folders`.
+    ///
+    /// A folder is recorded file by file, so each one can be checked and put back on undo.
+    pub fn record(
+        &self,
+        operation: Operation,
+        source: &Path,
+        destination: &Path,
+        created_folders: &[PathBuf],
+    ) {
+        let result = if destination.is_dir() {
+            files_in(destination).and_then(|files| {
+                files.iter().try_for_each(|file| {
+                    let destination_file = destination.join(file);
+                    // The folder and everything in it are new as well
+                    let created: Vec<PathBuf> = destination_file
+                        .ancestors()
+                        .skip(1)
+                        .take_while(|folder| folder.starts_with(destination))
+                        .map(Path::to_path_buf)
+                        .chain(created_folders.iter().cloned())
+                        .collect();
+                    self.try_record(operation, &source.join(file), &destination_file, created)
+                })
+            })
+        } else {
+            self.try_record(operation, source, destination, created_folders.to_vec())
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Warning: Failed to write journal {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    /// Move a file (or folder) that is being removed from the library into a `removed` folder
+    /// next to the journal, recording the move so that undo puts it back
+    pub fn set_aside(&self, path: &Path) -> io::Result<PathBuf> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let mut aside = self
+            .path
+            .with_file_name("removed")
+            .join(&self.run_id)
+            .join(name);
+        if aside.exists() {
+            aside = numbered_path(&aside, &|_| false);
+        }
+        let created = missing_folders(&aside);
+        if !transfer(Operation::Move, path, &aside) {
+            return Err(io::Error::other(format!(
+                "could not move it to {}",
+                aside.display()
+            )));
+        }
+        self.record(Operation::Move, path, &aside, &created);
+        Ok(aside)
+    }
+
+    fn try_record(
+        &self,
+        operation: Operation,
+        source: &Path,
+        destination: &Path,
+        created_folders: Vec<PathBuf>,
+    ) -> io::Result<()> {
+        let record = JournalRecord {
+            run_id: self.run_id.clone(),
+            timestamp: now(),
+            operation,
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            size: fs::metadata(destination)?.len(),
+            hash: hash_file(destination)?,
+            created_folders,
+        };
+        let line = serde_json::to_string(&record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{line}")?;
+        self.recorded.set(self.recorded.get() + 1);
+        Ok(())
+    }
+}
+
+pub fn load_records(path: &Path) -> io::Result<Vec<JournalRecord>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+fn save_records(path: &Path, records: &[JournalRecord]) -> io::Result<()> {
+    let mut contents = String::new();
+    for record in records {
+        let line = serde_json::to_string(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    fs::write(path, contents)
+}
+
+/// List each run in the journal in order, with the number of operations it made
+pub fn list_runs(records: &[JournalRecord]) -> Vec<(String, usize)> {
+    let mut runs: Vec<(String, usize)> = Vec::new();
+    for record in records {
+        match runs.iter_mut().find(|(id, _)| *id == record.run_id) {
+            Some((_, count)) => *count += 1,
+            None => runs.push((record.run_id.clone(), 1)),
+        }
+    }
+    runs
+}
+
+/// Remove the folders the operation created, once taking the file back out of the library
+/// has left them empty. Folders that were there before, like the library itself, are kept.
+fn remove_empty_parents(record: &JournalRecord) {
+    for folder in record.destination.ancestors().skip(1) {
+        if !record
+            .created_folders
+            .iter()
+            .any(|created| created == folder)
+            || fs::remove_dir(folder).is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Reverse a single journal record. Returns true if the record was undone.
+fn undo_record(record: &JournalRecord) -> bool {
+    let destination = &record.destination;
+    match hash_file(destination) {
+        Ok(hash) if hash == record.hash => {}
+        Ok(_) => {
+            eprintln!(
+                "{} has changed since it was organised, leaving it alone",
+                destination.display()
+            );
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Can't read {}: {e}", destination.display());
+            return false;
+        }
+    }
+
+    let undone = match record.operation {
+        Operation::Move => {
+            if record.source.exists() {
+                eprintln!(
+                    "{} already exists, not moving {} back",
+                    record.source.display(),
+                    destination.display()
+                );
+                false
+            } else {
+                transfer(Operation::Move, destination, &record.source)
+            }
+        }
+        Operation::Copy => match fs::remove_file(destination) {
+            Ok(()) => {
+                println!("Removed copy {}", destination.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to remove {}: {e}", destination.display());
+                false
+            }
+        },
+    };
+    if undone {
+        remove_empty_parents(record);
+    }
+    undone
+}
+
+/// Reverse every operation of a run, newest first, and drop the undone records from the journal.
+///
+/// If no run ID is given the most recent run is undone. Returns the number of operations reversed.
+pub fn undo(path: &Path, run_id: Option<&str>) -> io::Result<usize> {
+    let records = load_records(path)?;
+    let run_id = match run_id {
+        Some(run_id) => run_id.to_owned(),
+        None => match records.last() {
+            Some(record) => record.run_id.clone(),
+            None => return Ok(0),
+        },
+    };
+    if !records.iter().any(|r| r.run_id == run_id) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no run with ID {run_id} in the journal"),
+        ));
+    }
+    println!("Undoing run {run_id}");
+
+    let mut undone = vec![false; records.len()];
+    for (index, record) in records.iter().enumerate().rev() {
+        if record.run_id == run_id {
+            undone[index] = undo_record(record);
+        }
+    }
+
+    let remaining: Vec<JournalRecord> = records
+        .into_iter()
+        .zip(&undone)
+        .filter(|(_, undone)| !**undone)
+        .map(|(record, _)| record)
+        .collect();
+    save_records(path, &remaining)?;
+    Ok(undone.iter().filter(|u| **u).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "ebook_organiser_journal_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_undo_move_and_copy() -> io::Result<()> {
+        let root = temp_dir();
+        let journal_path = root.join("journal.jsonl");
+        let moved_source = root.join("in/moved.epub");
+        let copied_source = root.join("in/copied.epub");
+        let moved_dest = root.join("lib/Author/Moved.epub");
+        let copied_dest = root.join("lib/Author/Copied.epub");
+        fs::create_dir_all(root.join("in"))?;
+        fs::write(&moved_source, "moved")?;
+        fs::write(&copied_source, "copied")?;
+
+        // The library was there before the run, so it stays after the undo
+        fs::create_dir_all(root.join("lib"))?;
+
+        let journal = Journal::new(&journal_path);
+        assert!(!journal.has_records());
+        let created = missing_folders(&moved_dest);
+        assert!(transfer(Operation::Move, &moved_source, &moved_dest));
+        journal.record(Operation::Move, &moved_source, &moved_dest, &created);
+        assert!(journal.has_records());
+        let created = missing_folders(&copied_dest);
+        assert!(transfer(Operation::Copy, &copied_source, &copied_dest));
+        journal.record(Operation::Copy, &copied_source, &copied_dest, &created);
+
+        assert_eq!(
+            list_runs(&load_records(&journal_path)?),
+            vec![(journal.run_id().to_owned(), 2)]
+        );
+        assert_eq!(undo(&journal_path, None)?, 2);
+
+        assert_eq!(fs::read_to_string(&moved_source)?, "moved");
+        assert!(copied_source.exists());
+        assert!(!root.join("lib/Author").exists());
+        assert!(root.join("lib").exists());
+        assert!(load_records(&journal_path)?.is_empty());
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_set_aside() -> io::Result<()> {
+        let root = temp_dir();
+        let journal_path = root.join("journal.jsonl");
+        let existing = root.join("lib/Book.epub");
+        fs::create_dir_all(root.join("lib"))?;
+        fs::write(&existing, "existing")?;
+
+        let journal = Journal::new(&journal_path);
+        let aside = journal.set_aside(&existing)?;
+        assert!(!existing.exists());
+        assert!(aside.starts_with(root.join("removed")));
+
+        assert_eq!(undo(&journal_path, None)?, 1);
+        assert_eq!(fs::read_to_string(&existing)?, "existing");
+        assert!(!root.join("removed").exists());
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_leaves_modified_files() -> io::Result<()> {
+        let root = temp_dir();
+        let journal_path = root.join("journal.jsonl");
+        let source = root.join("book.epub");
+        let dest = root.join("lib/Book.epub");
+        fs::write(&source, "original")?;
+
+        let journal = Journal::new(&journal_path);
+        let created = missing_folders(&dest);
+        assert!(transfer(Operation::Move, &source, &dest));
+        journal.record(Operation::Move, &source, &dest, &created);
+        fs::write(&dest, "edited")?;
+
+        assert_eq!(undo(&journal_path, Some(journal.run_id()))?, 0);
+        assert!(dest.exists());
+        assert_eq!(load_records(&journal_path)?.len(), 1);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
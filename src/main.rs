@@ -1,11 +1,24 @@
 use clap::{Parser, Subcommand};
+use prompt::Interactor;
 use std::path::{Path, PathBuf};
 use std::process;
 
+mod aliases;
+mod collision;
 mod config;
+mod duplicates;
+mod fileops;
+mod hash;
+mod journal;
+mod names;
 mod parsers;
+mod plan;
 mod prompt;
+mod review;
+mod routing;
+mod sanitise;
 mod sorter;
+mod template;
 
 /// Ebook Organiser - A tool to automatically organise your ebook collection
 ///
@@ -49,8 +62,102 @@ enum Commands {
         /// Move files instead of copying them (overrides config)
         #[arg(long, conflicts_with = "copy")]
         r#move: bool,
+
+        /// What to do when a destination already exists (overrides config)
+        #[arg(long, value_enum, value_name = "POLICY")]
+        on_collision: Option<collision::CollisionPolicy>,
+
+        /// Move or copy every file without asking for confirmation (overrides config)
+        ///
+        /// Missing metadata, collisions and duplicates are still asked about, depending
+        /// on their policies.
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Never prompt, for running from cron or a timer
+        ///
+        /// Files with complete metadata are sorted without confirmation. Files that would
+        /// need a prompt are left alone and added to the review queue, to be sorted later
+        /// with the `review` command.
+        #[arg(long)]
+        non_interactive: bool,
+
+        /// Print what would be done without moving or copying any files
+        ///
+        /// Every file is still parsed (and may still prompt for missing metadata),
+        /// but the resulting plan is only printed, including collisions and skipped files.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Write the dry run plan to this file as JSON instead of printing it
+        #[arg(long, value_name = "FILE", requires = "dry_run")]
+        json: Option<PathBuf>,
+    },
+
+    /// Work out how files would be organised and save the plan for review
+    ///
+    /// The plan is a JSON file listing each source file, its destination, the operation
+    /// and the parsed metadata. It can be edited by hand and then carried out with `apply`.
+    Plan {
+        /// Path to source directory containing ebooks to organise
+        #[arg(value_name = "SOURCE_PATH")]
+        source_path: Option<PathBuf>,
+
+        /// File to write the plan to
+        #[arg(short, long, value_name = "FILE")]
+        out: PathBuf,
+
+        /// Plan to copy files instead of moving them (overrides config)
+        #[arg(long, conflicts_with = "move")]
+        copy: bool,
+
+        /// Plan to move files instead of copying them (overrides config)
+        #[arg(long, conflicts_with = "copy")]
+        r#move: bool,
+
+        /// What to do when a destination already exists (overrides config)
+        ///
+        /// `ask` leaves collisions in the plan for review.
+        #[arg(long, value_enum, value_name = "POLICY")]
+        on_collision: Option<collision::CollisionPolicy>,
+    },
+
+    /// Carry out a plan saved by the `plan` command
+    ///
+    /// Files are not parsed again and no confirmation is asked for. Only entries with a
+    /// "pending" status are acted on.
+    Apply {
+        /// Path to the plan file
+        #[arg(value_name = "PLAN_FILE")]
+        plan_path: PathBuf,
+    },
+
+    /// Sort the files that a non-interactive run left for review
+    ///
+    /// Each file is shown with the questions it needed answering and the options it would
+    /// have been given, then sorted as usual.
+    Review,
+
+    /// Reverse the moves and copies made by a previous run
+    ///
+    /// Every run records its file operations in the journal. Files that have changed
+    /// since they were organised are left alone.
+    Undo {
+        /// ID of the run to undo, defaults to the most recent run
+        #[arg(value_name = "RUN_ID")]
+        run_id: Option<String>,
+
+        /// List the runs recorded in the journal instead of undoing one
+        #[arg(long, conflicts_with = "run_id")]
+        list: bool,
     },
 
+    /// Check the configuration file, including its templates
+    ///
+    /// Reports unknown variables, unbalanced braces and templates that could lead
+    /// outside of the library, along with where in the template the problem is.
+    CheckConfig,
+
     /// Save the default configuration file
     ///
     /// Creates a default configuration file at the system's default config location
@@ -64,17 +171,24 @@ fn get_default_config_path() -> PathBuf {
     dirs::config_dir().unwrap().join("ebook-organiser.toml")
 }
 
-/// Run the sort operation with the specified config and source path
-fn run_sort(config_path: Option<PathBuf>, source_path: Option<PathBuf>, copy_flag: Option<bool>) {
-    // Load configuration from specified path or default path
+/// How a sort run should report its results
+enum SortMode {
+    /// Move or copy the files
+    Apply,
+    /// Only print the plan, optionally saving it as JSON
+    DryRun { json: Option<PathBuf> },
+}
+
+/// Load configuration from specified path or default path, falling back to the defaults
+fn load_config(config_path: Option<PathBuf>) -> config::Config {
     let config_path = config_path.unwrap_or_else(get_default_config_path);
 
-    let mut config = match config::Config::load(&config_path) {
+    match config::Config::load(&config_path) {
         Ok(config) => {
             println!("Configuration loaded from {}", config_path.display());
             config
         }
-        Err(e) => {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             println!(
                 "Notice: Failed to load configuration from {}: {}",
                 config_path.display(),
@@ -83,7 +197,74 @@ fn run_sort(config_path: Option<PathBuf>, source_path: Option<PathBuf>, copy_fla
             println!("Using default configuration values instead.");
             config::Config::default()
         }
-    };
+        Err(e) => {
+            // Carrying on with the defaults could sort files somewhere unexpected
+            eprintln!(
+                "Error: Invalid configuration in {}:\n{e}",
+                config_path.display()
+            );
+            eprintln!("Run `ebook-organiser check-config` after fixing it.");
+            process::exit(1);
+        }
+    }
+}
+
+/// Check the config file and its templates without touching any files
+fn run_check_config(config_path: Option<PathBuf>) {
+    let config_path = config_path.unwrap_or_else(get_default_config_path);
+    match config::Config::load(&config_path) {
+        Ok(config) => {
+            println!("Configuration in {} is valid.", config_path.display());
+            println!("Ebook template: {}", config.format_template);
+            println!("Audiobook template: {}", config.audiobook_template());
+            println!("Comic template: {}", config.comic_template());
+            for (extension, template) in &config.templates {
+                println!("Template for .{extension} files: {template}");
+            }
+            for (i, rule) in config.rules.iter().enumerate() {
+                println!("Rule {i} sends matching files to {}", rule.library);
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Error: Invalid configuration in {}:\n{e}",
+                config_path.display()
+            );
+            eprintln!(
+                "Templates can use: {}",
+                sorter::template_variables()
+                    .iter()
+                    .map(|name| format!("{{{name}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Run the sort operation with the specified config and source path
+fn run_sort(
+    config_path: Option<PathBuf>,
+    source_path: Option<PathBuf>,
+    copy_flag: Option<bool>,
+    on_collision: Option<collision::CollisionPolicy>,
+    non_interactive: bool,
+    yes: bool,
+    mode: SortMode,
+) {
+    let mut aliases = load_aliases(config_path.as_deref());
+    // A dry run leaves everything on disk as it was, the alias file included
+    if matches!(mode, SortMode::DryRun { .. }) {
+        aliases = aliases.read_only();
+    }
+    let mut config = load_config(config_path);
+    if yes {
+        config.approve = sorter::ApprovePolicy::Always;
+    }
+    if let Some(policy) = on_collision {
+        config.collisions = policy;
+    }
 
     // Override config copy value if a command line flag was provided
     if let Some(copy_value) = copy_flag {
@@ -103,8 +284,18 @@ fn run_sort(config_path: Option<PathBuf>, source_path: Option<PathBuf>, copy_fla
     };
 
     let library_path = Path::new(&config.library_path);
-    let audio_book_library_path = Path::new(&config.audiobook_library_path);
-    let sorter = sorter::Sorter::new(&config.format_template, config.copy);
+    let libraries = libraries(&config);
+    let journal = journal::Journal::new(Path::new(&config.journal_path));
+    let interactor: Box<dyn prompt::Interactor> = if non_interactive {
+        Box::new(prompt::NonInteractive::default())
+    } else {
+        Box::new(prompt::Terminal::default())
+    };
+    let mut sorter = build_sorter(&config, &journal, interactor.as_ref(), &aliases);
+    let review_queue = non_interactive.then(|| load_review_queue(&config));
+    if let Some(review_queue) = &review_queue {
+        sorter = sorter.with_review_queue(review_queue);
+    }
 
     println!(
         "Starting organisation process: sorting ebooks from {} into {}",
@@ -117,9 +308,251 @@ fn run_sort(config_path: Option<PathBuf>, source_path: Option<PathBuf>, copy_fla
         if config.copy { "enabled" } else { "disabled" }
     );
 
-    sorter.sort_recursively(&source_path, library_path, audio_book_library_path);
+    match mode {
+        SortMode::Apply => {
+            sorter.sort_recursively(&source_path, &libraries);
+            if sorter.stopped() {
+                println!("Stopped, the remaining files were left alone.");
+            } else {
+                println!("Organisation complete!");
+            }
+            print_undo_hint(&journal);
+            if let Some(review_queue) = &review_queue {
+                save_review_queue(review_queue);
+                if !review_queue.is_empty() {
+                    println!(
+                        "{} files need review, run `ebook-organiser review` to sort them.",
+                        review_queue.len()
+                    );
+                }
+            }
+        }
+        SortMode::DryRun { json } => {
+            let plan = sorter.plan_recursively(&source_path, &libraries);
+            match json {
+                Some(json_path) => {
+                    if let Err(e) = plan.save(&json_path) {
+                        eprintln!(
+                            "Error: Failed to write plan to {}: {e}",
+                            json_path.display()
+                        );
+                        process::exit(1);
+                    }
+                    println!("Plan written to {}", json_path.display());
+                    println!(
+                        "Review it, then run `ebook-organiser apply {}` to carry it out.",
+                        json_path.display()
+                    );
+                }
+                None => plan.print(),
+            }
+            println!("Dry run complete, no files were changed.");
+        }
+    }
+}
+
+/// The library each kind of file is sorted into
+fn libraries(config: &config::Config) -> sorter::Libraries<'_> {
+    sorter::Libraries {
+        ebooks: sorter::Library {
+            root: Path::new(&config.library_path),
+            template: &config.format_template,
+        },
+        audiobooks: sorter::Library {
+            root: Path::new(&config.audiobook_library_path),
+            template: config.audiobook_template(),
+        },
+        comics: sorter::Library {
+            root: Path::new(&config.comic_library_path),
+            template: config.comic_template(),
+        },
+    }
+}
+
+fn build_sorter<'a>(
+    config: &'a config::Config,
+    journal: &'a journal::Journal,
+    interactor: &'a dyn prompt::Interactor,
+    aliases: &'a aliases::AuthorAliases,
+) -> sorter::Sorter<'a> {
+    sorter::Sorter::new(config.copy, interactor)
+        .with_author_aliases(aliases)
+        .with_journal(journal)
+        .with_extension_templates(&config.templates)
+        .with_rules(&config.rules)
+        .with_duplicate_policy(config.duplicates)
+        .with_collision_policy(config.collisions)
+        .with_approve_policy(config.approve)
+        .with_sanitiser(sanitise::Sanitiser::new(
+            config.path_style,
+            config.max_component_length,
+        ))
+}
+
+/// Load the author aliases kept next to the config file
+fn load_aliases(config_path: Option<&Path>) -> aliases::AuthorAliases {
+    let path = config_path
+        .map_or_else(get_default_config_path, Path::to_path_buf)
+        .with_file_name("ebook-organiser-authors.toml");
+    match aliases::AuthorAliases::load(&path) {
+        Ok(aliases) => aliases,
+        Err(e) => {
+            eprintln!(
+                "Error: Failed to read author aliases {}: {e}",
+                path.display()
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn load_review_queue(config: &config::Config) -> review::ReviewQueue {
+    let path = Path::new(&config.review_queue_path);
+    match review::ReviewQueue::load(path) {
+        Ok(review_queue) => review_queue,
+        Err(e) => {
+            eprintln!("Error: Failed to read review queue {}: {e}", path.display());
+            process::exit(1);
+        }
+    }
+}
+
+fn save_review_queue(review_queue: &review::ReviewQueue) {
+    if let Err(e) = review_queue.save() {
+        eprintln!(
+            "Warning: Failed to write review queue {}: {e}",
+            review_queue.path().display()
+        );
+    }
+}
+
+/// Walk through the files a non-interactive run put off, sorting each one interactively
+fn run_review(config_path: Option<PathBuf>) {
+    let aliases = load_aliases(config_path.as_deref());
+    let config = load_config(config_path);
+    let review_queue = load_review_queue(&config);
+    if review_queue.is_empty() {
+        println!("Nothing to review.");
+        return;
+    }
+    let journal = journal::Journal::new(Path::new(&config.journal_path));
+    let terminal = prompt::Terminal::default();
+    let sorter = build_sorter(&config, &journal, &terminal, &aliases);
+
+    for entry in review_queue.entries() {
+        if entry.source.exists() {
+            println!("{} was left for review:", entry.source.display());
+            for question in &entry.questions {
+                println!("  {}", question.prompt);
+                for option in &question.options {
+                    println!("    - {option}");
+                }
+            }
+            match terminal.ask_bool("Sort it now? (y/n)") {
+                Some(true) => {}
+                // Left in the queue for next time
+                Some(false) => continue,
+                None => break,
+            }
+            let library = sorter::Library {
+                root: &entry.library,
+                template: &entry.template,
+            };
+            let sorted = sorter.sort(&entry.source, &entry.source_root, &library);
+            // The file that was being asked about stays in the queue
+            if sorter.stopped() {
+                break;
+            }
+            // Declined, skipped or unreadable files stay in the queue for next time
+            if !sorted {
+                continue;
+            }
+        } else {
+            println!(
+                "{} no longer exists, removing it from the queue.",
+                entry.source.display()
+            );
+        }
+        // Saved after every file, so stopping part way through keeps the rest of the queue
+        review_queue.remove(&entry.source);
+        save_review_queue(&review_queue);
+    }
+    println!("Review complete!");
+    print_undo_hint(&journal);
+}
+
+/// Print the run ID to undo with, if the run changed anything
+fn print_undo_hint(journal: &journal::Journal) {
+    if !journal.has_records() {
+        return;
+    }
+    println!(
+        "Run ID: {0} (use `ebook-organiser undo {0}` to reverse it)",
+        journal.run_id()
+    );
+}
+
+/// Carry out a previously saved plan
+fn run_apply(config_path: Option<PathBuf>, plan_path: &Path) {
+    let config = load_config(config_path);
+    let plan = match plan::Plan::load(plan_path) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!(
+                "Error: Failed to load plan from {}: {e}",
+                plan_path.display()
+            );
+            process::exit(1);
+        }
+    };
+    let journal = journal::Journal::new(Path::new(&config.journal_path));
+    let done = plan.apply(&journal);
+    println!("Plan applied, {done} files organised.");
+    print_undo_hint(&journal);
+}
+
+/// Undo a previous run, or list the runs that can be undone
+fn run_undo(config_path: Option<PathBuf>, run_id: Option<&str>, list: bool) {
+    let config = load_config(config_path);
+    let journal_path = Path::new(&config.journal_path);
 
-    println!("Organisation complete!");
+    if list {
+        match journal::load_records(journal_path) {
+            Ok(records) => {
+                for (run_id, count) in journal::list_runs(&records) {
+                    println!("{run_id}: {count} files");
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "Error: Failed to read journal {}: {e}",
+                    journal_path.display()
+                );
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match journal::undo(journal_path, run_id) {
+        Ok(0) => println!("Nothing was undone."),
+        Ok(count) => println!("Undo complete, {count} files restored."),
+        Err(e) => {
+            eprintln!("Error: Failed to undo: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Turn the mutually exclusive --copy/--move flags into an optional override
+fn copy_override(copy: bool, r#move: bool) -> Option<bool> {
+    if copy {
+        Some(true)
+    } else if r#move {
+        Some(false)
+    } else {
+        None
+    }
 }
 
 fn main() {
@@ -143,21 +576,60 @@ fn main() {
             source_path,
             copy,
             r#move,
+            dry_run,
+            json,
+            on_collision,
+            non_interactive,
+            yes,
         }) => {
             // Determine whether to override the copy flag from config
-            let copy_flag = if *copy {
-                Some(true)
-            } else if *r#move {
-                Some(false)
+            let copy_flag = copy_override(*copy, *r#move);
+
+            let mode = if *dry_run {
+                SortMode::DryRun { json: json.clone() }
             } else {
-                None
+                SortMode::Apply
             };
 
-            run_sort(cli.config.clone(), source_path.clone(), copy_flag);
+            run_sort(
+                cli.config.clone(),
+                source_path.clone(),
+                copy_flag,
+                *on_collision,
+                *non_interactive,
+                *yes,
+                mode,
+            );
+        }
+        Some(Commands::Plan {
+            source_path,
+            out,
+            copy,
+            r#move,
+            on_collision,
+        }) => {
+            let mode = SortMode::DryRun {
+                json: Some(out.clone()),
+            };
+            run_sort(
+                cli.config.clone(),
+                source_path.clone(),
+                copy_override(*copy, *r#move),
+                *on_collision,
+                false,
+                false,
+                mode,
+            );
+        }
+        Some(Commands::Review) => run_review(cli.config.clone()),
+        Some(Commands::CheckConfig) => run_check_config(cli.config.clone()),
+        Some(Commands::Apply { plan_path }) => run_apply(cli.config.clone(), plan_path),
+        Some(Commands::Undo { run_id, list }) => {
+            run_undo(cli.config.clone(), run_id.as_deref(), *list)
         }
         None => {
             // If no command is specified, default to Sort with no source path
-            run_sort(cli.config, None, None);
+            run_sort(cli.config, None, None, None, false, false, SortMode::Apply);
         }
     }
 }
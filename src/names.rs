@@ -0,0 +1,295 @@
+/// Words that end a name rather than being part of it, as in "Smith, John, Jr."
+const SUFFIXES: &[&str] = &["jr", "sr", "ii", "iii", "iv"];
+
+/// Words that belong with the surname, as in "Vincent van Gogh" or "Ursula K. Le Guin"
+const PARTICLES: &[&str] = &[
+    "van", "von", "der", "den", "de", "del", "della", "di", "da", "du", "des", "la", "le", "ten",
+    "ter", "dos", "das",
+];
+
+/// The usual way of writing a suffix such as "jr" or "iii"
+fn suffix(word: &str) -> Option<String> {
+    let bare = word.trim_end_matches('.').to_lowercase();
+    match bare.as_str() {
+        "jr" => Some(String::from("Jr.")),
+        "sr" => Some(String::from("Sr.")),
+        _ if SUFFIXES.contains(&bare.as_str()) => Some(bare.to_uppercase()),
+        _ => None,
+    }
+}
+
+fn is_particle(word: &str) -> bool {
+    PARTICLES.contains(&word.to_lowercase().as_str())
+}
+
+/// "O'BRIEN" becomes "O'Brien" and "JEAN-PAUL" becomes "Jean-Paul"
+fn title_case(word: &str) -> String {
+    let mut cased = String::with_capacity(word.len());
+    let mut start = true;
+    for c in word.chars() {
+        if start {
+            cased.extend(c.to_uppercase());
+        } else {
+            cased.extend(c.to_lowercase());
+        }
+        start = matches!(c, '-' | '\'' | '’');
+    }
+    cased
+}
+
+/// Whether an ALL CAPS word is better left as it is: one with other characters in it, like
+/// "AC/DC", or a short acronym with no vowels, like "BBC"
+fn keep_caps(word: &str) -> bool {
+    let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+    word.chars()
+        .any(|c| !c.is_alphabetic() && !matches!(c, '-' | '\'' | '’' | '.'))
+        || (letters <= 4 && !word.chars().any(|c| "AEIOUY".contains(c)))
+}
+
+/// Tidy the words of one name already in reading order: "J.R.R." is spaced out as
+/// "J. R. R.", an ALL CAPS name is re-cased and suffixes are moved to the end
+fn tidy(words: &[&str]) -> String {
+    let mut tidied: Vec<String> = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        let letters: Vec<&str> = word.split_inclusive('.').collect();
+        if letters.len() > 1
+            && letters
+                .iter()
+                .all(|l| l.ends_with('.') && l.chars().count() == 2)
+        {
+            tidied.extend(letters.iter().map(|l| l.to_string()));
+        } else if i + 1 < words.len()
+            && word.chars().count() == 1
+            && word.chars().all(char::is_uppercase)
+        {
+            // A lone initial, unless it is the whole surname as in "Malcolm X"
+            tidied.push(format!("{word}."));
+        } else {
+            tidied.push(word.to_string());
+        }
+    }
+
+    let all_caps = !tidied
+        .iter()
+        .any(|word| word.chars().any(char::is_lowercase))
+        && tidied.iter().any(|word| {
+            suffix(word).is_none() && word.chars().filter(|c| c.is_alphabetic()).count() > 1
+        });
+    if all_caps {
+        for (i, word) in tidied.iter_mut().enumerate() {
+            if i > 0 && is_particle(word) {
+                *word = word.to_lowercase();
+            } else if suffix(word).is_none() && !keep_caps(word) {
+                *word = title_case(word);
+            }
+        }
+    }
+
+    let mut suffixes = Vec::new();
+    let mut name = Vec::new();
+    for (i, word) in tidied.into_iter().enumerate() {
+        match suffix(&word) {
+            Some(suffix) if i > 0 => suffixes.push(suffix),
+            _ => name.push(word),
+        }
+    }
+    name.extend(suffixes);
+    name.join(" ")
+}
+
+/// Split a name on its commas, putting any suffix with the part before it
+fn comma_parts(name: &str) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    for part in name.split(',') {
+        let part = part.split_whitespace().collect::<Vec<_>>().join(" ");
+        if part.is_empty() {
+            continue;
+        }
+        match parts.last_mut() {
+            Some(previous) if suffix(&part).is_some() => {
+                previous.push(' ');
+                previous.push_str(&part);
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts
+}
+
+/// The words of a name that aren't particles or suffixes
+fn main_words(name: &str) -> usize {
+    name.split_whitespace()
+        .filter(|word| !is_particle(word) && suffix(word).is_none())
+        .count()
+}
+
+/// Tidy up one person's name, turning "Last, First" around: "Tolkien, J.R.R." becomes
+/// "J. R. R. Tolkien" and "Smith, John, Jr." becomes "John Smith Jr."
+pub fn normalise_name(name: &str) -> String {
+    let parts = comma_parts(name);
+    let reading_order = match parts.split_first() {
+        Some((last, first)) if !first.is_empty() => format!("{} {last}", first.join(" ")),
+        _ => parts.join(" "),
+    };
+    tidy(&reading_order.split_whitespace().collect::<Vec<_>>())
+}
+
+/// Whether the commas in a name separate people, as in "Terry Pratchett, Neil Gaiman, Stephen
+/// Baxter", rather than a surname from the rest, as in "Garcia Marquez, Gabriel José".
+///
+/// Two parts are only taken as two people when `listed`, that is when the value also uses
+/// ";", "&" or "and", and neither part looks like a surname on its own.
+fn is_comma_list(parts: &[String], listed: bool) -> bool {
+    match parts {
+        [] | [_] => false,
+        [last, first] => listed && main_words(last) > 1 && main_words(first) > 1,
+        _ => true,
+    }
+}
+
+/// The people in a list of names, whether joined with ";", "&", "and" or commas, each tidied
+/// up with `normalise_name`
+pub fn split_authors(value: &str) -> Vec<String> {
+    let groups: Vec<Vec<String>> = value
+        .split([';', '&'])
+        .flat_map(|group| {
+            let words: Vec<&str> = group.split_whitespace().collect();
+            words
+                .split(|word| word.eq_ignore_ascii_case("and"))
+                .map(|name| comma_parts(&name.join(" ")))
+                .collect::<Vec<_>>()
+        })
+        .filter(|parts| !parts.is_empty())
+        .collect();
+    let listed = groups.len() > 1;
+    let mut names: Vec<String> = Vec::new();
+    for parts in groups {
+        if is_comma_list(&parts, listed) {
+            names.extend(parts.iter().map(|part| normalise_name(part)));
+        } else {
+            names.push(normalise_name(&parts.join(", ")));
+        }
+    }
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+/// Tidy up an author field that may name several people, joining them with " & "
+pub fn normalise_authors(value: &str) -> String {
+    split_authors(value).join(" & ")
+}
+
+/// "J.R.R. Tolkien" becomes "Tolkien, J.R.R.", keeping particles with the surname ("van Gogh,
+/// Vincent") and suffixes at the end ("Smith, John, Jr."). Names that already have a comma are
+/// left alone, and each name in an "&" list is turned around.
+pub fn sort_name(name: &str) -> String {
+    let names: Vec<String> = name
+        .split('&')
+        .map(|name| {
+            let name = name.trim();
+            if name.contains(',') {
+                return name.to_owned();
+            }
+            let words: Vec<&str> = name.split_whitespace().collect();
+            let end = words
+                .iter()
+                .rposition(|word| suffix(word).is_none())
+                .map_or(words.len(), |i| i + 1);
+            let (words, suffixes) = words.split_at(end);
+            if words.len() < 2 {
+                return name.to_owned();
+            }
+            let mut start = words.len() - 1;
+            while start > 1 && is_particle(words[start - 1]) {
+                start -= 1;
+            }
+            let mut sorted = format!("{}, {}", words[start..].join(" "), words[..start].join(" "));
+            for suffix in suffixes {
+                sorted.push_str(", ");
+                sorted.push_str(suffix);
+            }
+            sorted
+        })
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.join(" & ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalise_name() {
+        assert_eq!(normalise_name("Tolkien, J. R. R."), "J. R. R. Tolkien");
+        assert_eq!(normalise_name("Tolkien, J.R.R."), "J. R. R. Tolkien");
+        assert_eq!(normalise_name("Smith, John, Jr."), "John Smith Jr.");
+        assert_eq!(normalise_name("Smith, Jr., John"), "John Smith Jr.");
+        assert_eq!(
+            normalise_name("Beethoven, Ludwig van"),
+            "Ludwig van Beethoven"
+        );
+        assert_eq!(
+            normalise_name("LUDWIG VAN BEETHOVEN"),
+            "Ludwig van Beethoven"
+        );
+        assert_eq!(normalise_name("O'BRIEN,\u{a0}FLANN"), "Flann O'Brien");
+        assert_eq!(normalise_name("Henry Ford iii"), "Henry Ford III");
+        assert_eq!(normalise_name("Ursula K Le Guin"), "Ursula K. Le Guin");
+        assert_eq!(normalise_name("Malcolm X"), "Malcolm X");
+        assert_eq!(normalise_name("AC/DC"), "AC/DC");
+        assert_eq!(normalise_name("BBC RADIO"), "BBC Radio");
+    }
+
+    #[test]
+    fn test_split_authors() {
+        assert_eq!(
+            split_authors("Le Guin, Ursula K."),
+            vec!["Ursula K. Le Guin"]
+        );
+        assert_eq!(
+            split_authors("Garcia Marquez, Gabriel José"),
+            vec!["Gabriel José Garcia Marquez"]
+        );
+        assert_eq!(
+            split_authors("Terry Pratchett, Neil Gaiman, Stephen Baxter"),
+            vec!["Terry Pratchett", "Neil Gaiman", "Stephen Baxter"]
+        );
+        assert_eq!(
+            split_authors("Terry Pratchett, Neil Gaiman & Stephen Baxter"),
+            vec!["Terry Pratchett", "Neil Gaiman", "Stephen Baxter"]
+        );
+        assert_eq!(
+            split_authors("Pratchett, Terry; Gaiman, Neil"),
+            vec!["Terry Pratchett", "Neil Gaiman"]
+        );
+        assert_eq!(
+            split_authors("Terry Pratchett AND Neil Gaiman & Stephen Baxter"),
+            vec!["Terry Pratchett", "Neil Gaiman", "Stephen Baxter"]
+        );
+        assert_eq!(
+            split_authors("Garcia Marquez, Gabriel"),
+            vec!["Gabriel Garcia Marquez"]
+        );
+        assert_eq!(split_authors(" ; "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_sort_name() {
+        assert_eq!(sort_name("J.R.R. Tolkien"), "Tolkien, J.R.R.");
+        assert_eq!(sort_name("Vincent van Gogh"), "van Gogh, Vincent");
+        assert_eq!(sort_name("Ursula K. Le Guin"), "Le Guin, Ursula K.");
+        assert_eq!(sort_name("John Smith Jr."), "Smith, John, Jr.");
+        assert_eq!(
+            sort_name("Terry Pratchett & Neil Gaiman"),
+            "Pratchett, Terry & Gaiman, Neil"
+        );
+        assert_eq!(sort_name("Tolkien, J.R.R."), "Tolkien, J.R.R.");
+        assert_eq!(sort_name("Plato"), "Plato");
+    }
+}
@@ -3,14 +3,20 @@ use std::path::Path;
 use mp4ameta::{Data, DataIdent, Tag};
 
 use crate::{
-    parsers::metadata::FileMetadata,
-    prompt::{prompt, prompt_select_other},
+    aliases::AuthorAliases,
+    names::normalise_authors,
+    parsers::{
+        audiobook_folder::AudioTags,
+        metadata::FileMetadata,
+        misc::{clean_series_index, parse_asin, parse_isbn},
+    },
+    prompt::Interactor,
 };
-fn get_title(meta: &Tag, file_path: &Path) -> String {
-    if let Some(title) = meta.title() {
-        if !title.is_empty() {
-            return title.to_owned();
-        }
+fn get_title(meta: &Tag, file_path: &Path, interactor: &dyn Interactor) -> String {
+    if let Some(title) = meta.title()
+        && !title.is_empty()
+    {
+        return title.to_owned();
     }
     let fourcc_sti = mp4ameta::Fourcc([b'@', b's', b't', b'i']);
     let fourcc_alb = mp4ameta::Fourcc([0xA9, b'a', b'l', b'b']);
@@ -18,10 +24,10 @@ fn get_title(meta: &Tag, file_path: &Path) -> String {
     for (meta_name, meta_data) in meta.data() {
         match meta_name {
             DataIdent::Fourcc(fourcc) => {
-                if fourcc == &fourcc_sti || fourcc == &fourcc_alb {
-                    if let Data::Utf8(title) = meta_data {
-                        return title.to_owned();
-                    }
+                if (fourcc == &fourcc_sti || fourcc == &fourcc_alb)
+                    && let Data::Utf8(title) = meta_data
+                {
+                    return title.to_owned();
                 }
             }
             DataIdent::Freeform { mean, name } => {
@@ -29,27 +35,87 @@ fn get_title(meta: &Tag, file_path: &Path) -> String {
                     // Freeform data without mean or name, skip
                     continue;
                 }
-                if name == "SUBTITLE" || name == "TITLE" {
-                    if let Data::Utf8(title) = meta_data {
-                        return title.to_owned();
-                    }
+                if (name == "SUBTITLE" || name == "TITLE")
+                    && let Data::Utf8(title) = meta_data
+                {
+                    return title.to_owned();
                 }
             }
         }
     }
     // Fall through to asking
-    prompt(&format!(
-        "No title found for {}, please enter one:",
-        file_path.display()
+    interactor
+        .ask_text(&format!(
+            "No title found for {}, please enter one:",
+            file_path.display()
+        ))
+        .unwrap_or_default()
+}
+
+/// Find a freeform (----) atom by name, such as the SERIES tag written by Mp3tag
+fn freeform_text(meta: &Tag, wanted: &str) -> Option<String> {
+    meta.data().find_map(|(ident, data)| match (ident, data) {
+        (DataIdent::Freeform { name, .. }, Data::Utf8(value))
+            if name.eq_ignore_ascii_case(wanted) && !value.trim().is_empty() =>
+        {
+            Some(value.trim().to_owned())
+        }
+        _ => None,
+    })
+}
+
+/// Read the series from the freeform SERIES and SERIES-PART tags, or the movement atoms
+/// iTunes and Audiobookshelf use for it
+fn get_series(meta: &Tag) -> (Option<String>, Option<String>) {
+    if let Some(series) = freeform_text(meta, "SERIES") {
+        let index = freeform_text(meta, "SERIES-PART").and_then(|i| clean_series_index(&i));
+        return (Some(series), index);
+    }
+    match meta.movement().map(str::trim).filter(|m| !m.is_empty()) {
+        Some(movement) => (
+            Some(movement.to_owned()),
+            meta.movement_index().map(|i| i.to_string()),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Read the narrator from a freeform NARRATOR tag, or the composer as audiobook tools such as
+/// Audiobookshelf and Libation write it, unless the composer was taken as the author
+fn get_narrator(meta: &Tag, author: &str) -> Option<String> {
+    freeform_text(meta, "NARRATOR").or_else(|| {
+        meta.composer()
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && !c.eq_ignore_ascii_case(author.trim()))
+            .map(str::to_owned)
+    })
+}
+
+/// Chapters can be stored as a Nero chapter list or a QuickTime chapter track
+fn get_chapter_count(meta: &Tag) -> Option<usize> {
+    let count = meta.chapter_list().len().max(meta.chapter_track().len());
+    (count > 0).then_some(count)
+}
+
+/// Read the album and author tags without prompting
+pub fn read_audiobook_tags(file_path: &Path) -> Option<AudioTags> {
+    let tag = mp4ameta::Tag::read_from_path(file_path).ok()?;
+    Some(AudioTags::new(
+        tag.album(),
+        tag.artist().or_else(|| tag.composer()),
     ))
 }
 
-pub fn parse_audiobook(file_path: &Path) -> Result<FileMetadata, String> {
+pub fn parse_audiobook(
+    file_path: &Path,
+    interactor: &dyn Interactor,
+    aliases: &AuthorAliases,
+) -> Result<FileMetadata, String> {
     // Open the m4b file and parse its metadata
     match mp4ameta::Tag::read_from_path(file_path) {
         Ok(file_meta) => {
             // Extract title from mobi metadata
-            let title = get_title(&file_meta, file_path);
+            let title = get_title(&file_meta, file_path, interactor);
 
             let author = file_meta.artist();
             let artists: Vec<&str> = file_meta.artists().collect();
@@ -61,23 +127,46 @@ pub fn parse_audiobook(file_path: &Path) -> Result<FileMetadata, String> {
                 .map(|&s| s.to_owned())
                 .collect();
 
+            let known = |name: &str| aliases.canonical(&[name.to_owned()]);
             let selected_author = if let Some(author) = author {
-                author.to_owned()
+                known(author).unwrap_or_else(|| normalise_authors(author))
             } else if let Some(author) = composer {
-                author.to_owned()
+                known(author).unwrap_or_else(|| normalise_authors(author))
+            } else if let Some(canonical) = aliases.canonical(&artist_options) {
+                canonical
             } else {
-                prompt_select_other(
-                    &format!(
-                        "No author found for {}, please enter one:",
-                        file_path.display()
-                    ),
-                    &artist_options,
-                )
+                interactor
+                    .select(
+                        &format!(
+                            "No author found for {}, please enter one:",
+                            file_path.display()
+                        ),
+                        &artist_options
+                            .iter()
+                            .map(|artist| normalise_authors(artist))
+                            .collect::<Vec<_>>(),
+                    )
+                    .inspect(|chosen| aliases.remember(&artist_options, chosen))
+                    .unwrap_or_default()
             };
 
+            let (series, series_index) = get_series(&file_meta);
+
             Ok(FileMetadata {
                 title: title.trim().to_owned(),
                 main_author: selected_author.trim().to_owned(),
+                series,
+                series_index,
+                isbn: freeform_text(&file_meta, "ISBN").and_then(|i| parse_isbn(&i)),
+                asin: freeform_text(&file_meta, "ASIN").and_then(|a| parse_asin(&a)),
+                publisher: freeform_text(&file_meta, "PUBLISHER"),
+                language: freeform_text(&file_meta, "LANGUAGE"),
+                date: file_meta.year().map(str::to_owned),
+                subjects: file_meta.genres().map(str::to_owned).collect(),
+                narrator: get_narrator(&file_meta, &selected_author),
+                duration_seconds: Some(file_meta.duration().as_secs()).filter(|&s| s > 0),
+                chapter_count: get_chapter_count(&file_meta),
+                ..Default::default()
             })
         }
         Err(e) => {
@@ -0,0 +1,228 @@
+use std::{
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    names::normalise_authors,
+    parsers::{
+        audiobook::read_audiobook_tags,
+        metadata::{AUDIOBOOK_EXTENSIONS, FileMetadata},
+        misc::file_extension,
+        mp3::read_mp3_tags,
+        vorbis::read_vorbis_tags,
+    },
+    prompt::Interactor,
+};
+
+/// The tags that tie the chapter files of one audiobook together
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AudioTags {
+    pub album: Option<String>,
+    pub author: Option<String>,
+}
+
+impl AudioTags {
+    pub fn new(album: Option<&str>, author: Option<&str>) -> Self {
+        let clean = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+        };
+        AudioTags {
+            album: clean(album),
+            author: clean(author),
+        }
+    }
+}
+
+fn read_tags(file_path: &Path) -> Option<AudioTags> {
+    match file_extension(file_path)?.as_str() {
+        "m4a" | "m4b" => read_audiobook_tags(file_path),
+        "mp3" => read_mp3_tags(file_path),
+        "opus" | "ogg" | "flac" => read_vorbis_tags(file_path),
+        _ => None,
+    }
+}
+
+/// Files that travel with an audiobook's tracks when its folder is moved
+const COMPANION_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "cue", "nfo", "txt", "m3u", "m3u8", "log",
+];
+
+/// Whether everything in a folder belongs to its audiobook: no subfolders, and nothing but
+/// audio files, covers, cue sheets and the like, so that moving the folder moves nothing else
+fn only_audiobook_files(folder: &Path) -> bool {
+    let Ok(entries) = read_dir(folder) else {
+        return false;
+    };
+    entries.flatten().map(|entry| entry.path()).all(|path| {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if path.is_dir() {
+            return false;
+        }
+        hidden
+            || file_extension(&path).is_some_and(|ext| {
+                AUDIOBOOK_EXTENSIONS.contains(&ext.as_str())
+                    || COMPANION_EXTENSIONS.contains(&ext.as_str())
+            })
+    })
+}
+
+/// Compare tags ignoring case and spacing, as rippers are not always consistent between tracks
+fn normalise(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The audio files directly inside a folder
+fn audio_files(folder: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            file_extension(path).is_some_and(|ext| AUDIOBOOK_EXTENSIONS.contains(&ext.as_str()))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Work out the shared album and author of a folder of tracks, if they all belong together
+fn shared_tags(tags: impl IntoIterator<Item = AudioTags>) -> Option<AudioTags> {
+    let mut shared = AudioTags::default();
+    let mut count = 0;
+    for track in tags {
+        let album = track.album?;
+        match &shared.album {
+            Some(first) if normalise(first) != normalise(&album) => return None,
+            Some(_) => {}
+            None => shared.album = Some(album),
+        }
+        // Untagged authors are fine, but two different authors means two different books
+        if let Some(author) = track.author {
+            match &shared.author {
+                Some(first) if normalise(first) != normalise(&author) => return None,
+                Some(_) => {}
+                None => shared.author = Some(author),
+            }
+        }
+        count += 1;
+    }
+    // A single file is sorted on its own like any other
+    if count < 2 { None } else { Some(shared) }
+}
+
+fn folder_tags(folder: &Path) -> Option<AudioTags> {
+    let files = audio_files(folder);
+    if files.len() < 2 {
+        return None;
+    }
+    let mut tags = Vec::new();
+    for file in &files {
+        tags.push(read_tags(file)?);
+    }
+    shared_tags(tags)
+}
+
+/// Check whether a folder holds the chapter files of a single audiobook: at least two audio
+/// files directly inside it, all tagged with the same album and by no more than one author.
+/// Folders that also hold other books or subfolders have their files sorted one by one.
+pub fn is_audiobook_folder(folder: &Path) -> bool {
+    folder.is_dir() && only_audiobook_files(folder) && folder_tags(folder).is_some()
+}
+
+pub fn parse_audiobook_folder(
+    folder: &Path,
+    interactor: &dyn Interactor,
+) -> Result<FileMetadata, String> {
+    let tags = folder_tags(folder).ok_or("not a single audiobook")?;
+    let title = tags.album.unwrap_or_default();
+    let selected_author = tags
+        .author
+        .map(|author| normalise_authors(&author))
+        .unwrap_or_else(|| {
+            interactor
+                .ask_text(&format!(
+                    "No author found for {}, please enter one:",
+                    folder.display()
+                ))
+                .unwrap_or_default()
+        });
+
+    Ok(FileMetadata {
+        title: title.trim().to_owned(),
+        main_author: selected_author.trim().to_owned(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_tags() {
+        let tracks = || {
+            vec![
+                AudioTags::new(Some("The Hobbit"), Some("J. R. R. Tolkien")),
+                AudioTags::new(Some("the  hobbit "), None),
+                AudioTags::new(Some("The Hobbit"), Some("j. r. r. tolkien")),
+            ]
+        };
+        let shared = shared_tags(tracks()).unwrap();
+        assert_eq!(shared.album.as_deref(), Some("The Hobbit"));
+        assert_eq!(shared.author.as_deref(), Some("J. R. R. Tolkien"));
+
+        // A different album, a different author or a single track are not one audiobook
+        let mut mixed = tracks();
+        mixed.push(AudioTags::new(Some("The Silmarillion"), None));
+        assert_eq!(shared_tags(mixed), None);
+        let mut mixed = tracks();
+        mixed.push(AudioTags::new(Some("The Hobbit"), Some("Andy Serkis")));
+        assert_eq!(shared_tags(mixed), None);
+        assert_eq!(shared_tags(tracks().into_iter().take(1)), None);
+    }
+
+    #[test]
+    fn test_only_audiobook_files() -> std::io::Result<()> {
+        let folder = std::env::temp_dir().join(format!(
+            "ebook_organiser_audiobook_folder_test_{}",
+            rand::random::<u64>()
+        ));
+        std::fs::create_dir_all(&folder)?;
+        for name in [
+            "01.mp3",
+            "02.MP3",
+            "cover.jpg",
+            "The Hobbit.cue",
+            ".DS_Store",
+        ] {
+            std::fs::write(folder.join(name), b"")?;
+        }
+        assert!(only_audiobook_files(&folder));
+        assert_eq!(
+            audio_files(&folder),
+            vec![folder.join("01.mp3"), folder.join("02.MP3")]
+        );
+
+        // Another book would be carried along with the audiobook
+        std::fs::write(folder.join("The Hobbit.epub"), b"")?;
+        assert!(!only_audiobook_files(&folder));
+        std::fs::remove_file(folder.join("The Hobbit.epub"))?;
+        std::fs::create_dir(folder.join("Extras"))?;
+        assert!(!only_audiobook_files(&folder));
+
+        std::fs::remove_dir_all(&folder)
+    }
+}
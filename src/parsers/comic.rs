@@ -0,0 +1,241 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::{
+    names::normalise_authors,
+    parsers::{metadata::FileMetadata, misc::clean_series_index},
+    prompt::Interactor,
+};
+
+/// The fields of a ComicInfo.xml that are used for sorting
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ComicInfo {
+    series: Option<String>,
+    number: Option<String>,
+    title: Option<String>,
+    writer: Option<String>,
+    year: Option<String>,
+}
+
+impl ComicInfo {
+    /// Fill any missing fields from another source
+    fn or(self, other: ComicInfo) -> ComicInfo {
+        ComicInfo {
+            series: self.series.or(other.series),
+            number: self.number.or(other.number),
+            title: self.title.or(other.title),
+            writer: self.writer.or(other.writer),
+            year: self.year.or(other.year),
+        }
+    }
+
+    /// Build a title such as "Saga #12 - Chapter Twelve (2013)"
+    fn full_title(&self) -> Option<String> {
+        let mut full_title = match (&self.series, &self.number) {
+            (Some(series), Some(number)) => format!("{series} #{number}"),
+            (Some(series), None) => series.clone(),
+            (None, _) => self.title.clone()?,
+        };
+        if self.series.is_some()
+            && let Some(title) = &self.title
+        {
+            full_title += &format!(" - {title}");
+        }
+        if let Some(year) = &self.year {
+            full_title += &format!(" ({year})");
+        }
+        Some(full_title)
+    }
+}
+
+fn parse_comic_info(xml: &str) -> Result<ComicInfo, String> {
+    let doc = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+    let field = |name: &str| {
+        doc.root_element()
+            .children()
+            .find(|n| n.has_tag_name(name))
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+    };
+    Ok(ComicInfo {
+        series: field("Series"),
+        number: field("Number"),
+        title: field("Title"),
+        // Writer is a comma separated list, the first one is used as the main author
+        writer: field("Writer")
+            .and_then(|w| w.split(',').next().map(|s| s.trim().to_owned()))
+            .filter(|s| !s.is_empty()),
+        year: field("Year"),
+    })
+}
+
+/// Read ComicInfo.xml from a CBZ archive
+fn read_cbz(file_path: &Path) -> Result<ComicInfo, String> {
+    let file = File::open(file_path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let name = archive
+        .file_names()
+        .find(|name| {
+            let file_name = name.rsplit('/').next().unwrap_or(name);
+            file_name.eq_ignore_ascii_case("ComicInfo.xml")
+        })
+        .map(str::to_owned)
+        .ok_or("no ComicInfo.xml in archive")?;
+    let mut xml = String::new();
+    archive
+        .by_name(&name)
+        .map_err(|e| e.to_string())?
+        .read_to_string(&mut xml)
+        .map_err(|e| e.to_string())?;
+    parse_comic_info(&xml)
+}
+
+fn is_year(token: &str) -> bool {
+    token.len() == 4
+        && token.chars().all(|c| c.is_ascii_digit())
+        && (token.starts_with("19") || token.starts_with("20"))
+}
+
+/// Guess series, issue number and year from a scene style name such as
+/// "Saga 012 (2013) (Digital) (Empire).cbr"
+fn infer_from_file_name(file_path: &Path) -> ComicInfo {
+    let stem = file_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().replace('_', " "))
+        .unwrap_or_default();
+
+    // Pull out the bracketed groups, keeping only a year if one is found
+    let mut year = None;
+    let mut name = String::new();
+    let mut depth = 0;
+    let mut group = String::new();
+    for c in stem.chars() {
+        match c {
+            '(' | '[' => {
+                depth += 1;
+                group.clear();
+            }
+            ')' | ']' if depth > 0 => {
+                depth -= 1;
+                if year.is_none() && is_year(group.trim()) {
+                    year = Some(group.trim().to_owned());
+                }
+            }
+            _ if depth > 0 => group.push(c),
+            _ => name.push(c),
+        }
+    }
+
+    let mut words: Vec<&str> = name.split_whitespace().collect();
+    let number = match words.last() {
+        Some(last)
+            if last
+                .trim_start_matches('#')
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '.')
+                && last.chars().any(|c| c.is_ascii_digit()) =>
+        {
+            let number = last.trim_start_matches('#').to_owned();
+            words.pop();
+            Some(number)
+        }
+        _ => None,
+    };
+    let series = words.join(" ").trim_end_matches(['-', ' ']).to_owned();
+
+    ComicInfo {
+        series: Some(series).filter(|s| !s.is_empty()),
+        number,
+        title: None,
+        writer: None,
+        year,
+    }
+}
+
+pub fn parse_comic(file_path: &Path, interactor: &dyn Interactor) -> Result<FileMetadata, String> {
+    // Only CBZ can be opened without external tools, the rest rely on the file name
+    let is_cbz = file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cbz"));
+    let info = if is_cbz {
+        match read_cbz(file_path) {
+            Ok(info) => info.or(infer_from_file_name(file_path)),
+            Err(e) => {
+                println!(
+                    "Failed to read ComicInfo.xml from {}: {e}",
+                    file_path.display()
+                );
+                infer_from_file_name(file_path)
+            }
+        }
+    } else {
+        infer_from_file_name(file_path)
+    };
+
+    let title = info.full_title().unwrap_or_else(|| {
+        interactor
+            .ask_text(&format!(
+                "No title found for {}, please enter one:",
+                file_path.display()
+            ))
+            .unwrap_or_default()
+    });
+    let selected_author = info
+        .writer
+        .map(|writer| normalise_authors(&writer))
+        .unwrap_or_else(|| {
+            interactor
+                .ask_text(&format!(
+                    "No writer found for {}, please enter one:",
+                    file_path.display()
+                ))
+                .unwrap_or_default()
+        });
+
+    Ok(FileMetadata {
+        title: title.trim().to_owned(),
+        main_author: selected_author.trim().to_owned(),
+        series_index: info
+            .series
+            .as_ref()
+            .and(info.number.as_deref())
+            .and_then(clean_series_index),
+        series: info.series,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comic_info() {
+        let xml = r#"<?xml version="1.0"?>
+        <ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+            <Title>Chapter Twelve</Title>
+            <Series>Saga</Series>
+            <Number>12</Number>
+            <Year>2013</Year>
+            <Writer>Brian K. Vaughan, Fiona Staples</Writer>
+        </ComicInfo>"#;
+        let info = parse_comic_info(xml).unwrap();
+        assert_eq!(info.writer.as_deref(), Some("Brian K. Vaughan"));
+        assert_eq!(
+            info.full_title().as_deref(),
+            Some("Saga #12 - Chapter Twelve (2013)")
+        );
+    }
+
+    #[test]
+    fn test_infer_from_file_name() {
+        let info = infer_from_file_name(Path::new("/in/Saga 012 (2013) (Digital) (Empire).cbr"));
+        assert_eq!(info.series.as_deref(), Some("Saga"));
+        assert_eq!(info.number.as_deref(), Some("012"));
+        assert_eq!(info.year.as_deref(), Some("2013"));
+
+        let info = infer_from_file_name(Path::new("/in/The_Walking_Dead_#100.cb7"));
+        assert_eq!(info.full_title().as_deref(), Some("The Walking Dead #100"));
+    }
+}
@@ -3,11 +3,91 @@ use std::{collections::HashSet, path::Path};
 use epub::doc::EpubDoc;
 
 use crate::{
-    parsers::{metadata::FileMetadata, misc::flip_comma_split},
-    prompt::prompt_select_other,
+    aliases::AuthorAliases,
+    names::{normalise_authors, normalise_name, split_authors},
+    parsers::{
+        metadata::FileMetadata,
+        misc::{clean_series_index, parse_asin, parse_isbn, parse_uuid},
+    },
+    prompt::Interactor,
 };
 
-pub fn parse_epub(file_path: &Path) -> Result<FileMetadata, String> {
+/// Read the series from an EPUB 3 collection, falling back to the calibre meta tags
+fn series<R: std::io::Read + std::io::Seek>(doc: &EpubDoc<R>) -> (Option<String>, Option<String>) {
+    let collection = doc
+        .metadata
+        .iter()
+        .filter(|m| m.property == "belongs-to-collection")
+        .find(|m| {
+            m.refinement("collection-type")
+                .is_none_or(|r| r.value == "series")
+        });
+    if let Some(collection) = collection
+        && !collection.value.trim().is_empty()
+    {
+        let index = collection
+            .refinement("group-position")
+            .and_then(|r| clean_series_index(&r.value));
+        return (Some(collection.value.trim().to_owned()), index);
+    }
+    let series = doc
+        .mdata("calibre:series")
+        .map(|m| m.value.trim().to_owned())
+        .filter(|s| !s.is_empty());
+    let index = series
+        .as_ref()
+        .and(doc.mdata("calibre:series_index"))
+        .and_then(|m| clean_series_index(&m.value));
+    (series, index)
+}
+
+/// The trimmed, non empty values of a metadata property such as "subject"
+fn values<R: std::io::Read + std::io::Seek>(doc: &EpubDoc<R>, property: &str) -> Vec<String> {
+    doc.metadata
+        .iter()
+        .filter(|m| m.property == property)
+        .map(|m| m.value.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// Sort the dc:identifier values into ISBN, ASIN and UUID, using the scheme when one is given
+fn identifiers<R: std::io::Read + std::io::Seek>(
+    doc: &EpubDoc<R>,
+) -> (Option<String>, Option<String>, Option<String>) {
+    let (mut isbn, mut asin, mut uuid) = (None, None, None);
+    for identifier in doc.metadata.iter().filter(|m| m.property == "identifier") {
+        // opf:scheme in EPUB 2, an identifier-type refinement in EPUB 3
+        let scheme = identifier
+            .refinement("scheme")
+            .or_else(|| identifier.refinement("identifier-type"))
+            .map(|r| r.value.to_lowercase());
+        let value = identifier.value.as_str();
+        match scheme.as_deref() {
+            Some("isbn") => isbn = isbn.or_else(|| parse_isbn(value)),
+            Some("asin" | "mobi-asin" | "amazon") => asin = asin.or_else(|| parse_asin(value)),
+            Some("uuid" | "calibre") => uuid = uuid.or_else(|| parse_uuid(value)),
+            _ => {
+                // Without a scheme only trust values that say what they are, or clearly are ISBNs
+                let lower = value.trim().to_lowercase();
+                if lower.starts_with("urn:asin:") {
+                    asin = asin.or_else(|| parse_asin(value));
+                } else if lower.starts_with("urn:uuid:") || parse_uuid(value).is_some() {
+                    uuid = uuid.or_else(|| parse_uuid(value));
+                } else {
+                    isbn = isbn.or_else(|| parse_isbn(value));
+                }
+            }
+        }
+    }
+    (isbn, asin, uuid)
+}
+
+pub fn parse_epub(
+    file_path: &Path,
+    interactor: &dyn Interactor,
+    aliases: &AuthorAliases,
+) -> Result<FileMetadata, String> {
     // This function would contain the logic to parse the EPUB file
     // For now, we will just return Ok to simulate successful parsing
     match EpubDoc::new(file_path) {
@@ -16,64 +96,74 @@ pub fn parse_epub(file_path: &Path) -> Result<FileMetadata, String> {
 
             let title = doc
                 .metadata
-                .get("title")
-                .and_then(|x| x.iter().find(|s| s.to_lowercase() != "unknown"))
-                .cloned()
+                .iter()
+                .filter(|m| m.property == "title")
+                .map(|m| m.value.clone())
+                .find(|s| s.to_lowercase() != "unknown")
                 .unwrap_or_else(|| "".to_string());
 
-            let creators = doc
+            let creators: Vec<String> = doc
                 .metadata
-                .get("creator")
-                .cloned()
-                .unwrap_or_else(std::vec::Vec::new);
-            let file_as = doc
+                .iter()
+                .filter(|m| m.property == "creator")
+                .map(|m| m.value.clone())
+                .collect();
+            let file_as: Vec<String> = doc
                 .metadata
-                .get("file-as")
-                .cloned()
-                .unwrap_or_else(std::vec::Vec::new);
+                .iter()
+                .filter(|m| m.property == "creator")
+                .filter_map(|m| m.refinement("file-as"))
+                .map(|r| r.value.clone())
+                .collect();
 
             let main_author = if creators.len() == 1 {
-                flip_comma_split(creators[0].clone())
+                aliases
+                    .canonical(&creators)
+                    .unwrap_or_else(|| normalise_authors(&creators[0]))
             } else if file_as.len() > 1 {
                 // If there are multiple authors, we can use the first one
                 // or format them in a specific way
-                flip_comma_split(file_as[0].clone())
+                aliases
+                    .canonical(&file_as[..1])
+                    .unwrap_or_else(|| normalise_name(&file_as[0]))
+            } else if let Some(canonical) =
+                aliases.canonical(&creators.iter().chain(&file_as).cloned().collect::<Vec<_>>())
+            {
+                canonical
             } else {
                 // Sometimes we get a bunch of names as CSV, or we get a single name with a comma and flipped order.
                 // For these cases we can prompt the user to pick
 
-                let options = creators.into_iter().chain(file_as).collect::<Vec<String>>();
-
-                let options_split1: Vec<String> = options
-                    .iter()
-                    .map(|s| flip_comma_split(s.to_owned()))
-                    .collect();
-                let options_split2: Vec<String> = options
-                    .iter()
-                    .map(|s| s.split(',').map(str::trim).collect::<Vec<_>>())
-                    .to_owned()
-                    .flatten()
-                    .map(|x| x.to_owned())
-                    .collect();
-
-                // Merge all threee options
-                let final_options_set: HashSet<String> = options
-                    .into_iter()
-                    .chain(options_split2)
-                    .chain(options_split1)
-                    .map(|x| x.trim().replace("  ", " "))
-                    .collect::<HashSet<_>>();
+                let raw = creators.into_iter().chain(file_as).collect::<Vec<String>>();
+                let final_options_set: HashSet<String> =
+                    raw.iter().flat_map(|s| split_authors(s)).collect();
                 let mut final_options: Vec<String> = final_options_set.into_iter().collect();
                 final_options.sort();
-                prompt_select_other(
-                    &format!("No main author found for {file_path:?}"),
-                    &final_options,
-                )
+                interactor
+                    .select(
+                        &format!("No main author found for {file_path:?}"),
+                        &final_options,
+                    )
+                    .inspect(|chosen| aliases.remember(&raw, chosen))
+                    .unwrap_or_default()
             };
 
+            let (series, series_index) = series(&doc);
+            let (isbn, asin, uuid) = identifiers(&doc);
+
             Ok(FileMetadata {
                 title: title.trim().to_owned(),
                 main_author: main_author.trim().to_owned(),
+                series,
+                series_index,
+                isbn,
+                asin,
+                uuid,
+                publisher: values(&doc, "publisher").into_iter().next(),
+                language: values(&doc, "language").into_iter().next(),
+                date: values(&doc, "date").into_iter().next(),
+                subjects: values(&doc, "subject"),
+                ..Default::default()
             })
         }
         Err(e) => {
@@ -82,3 +172,125 @@ pub fn parse_epub(file_path: &Path) -> Result<FileMetadata, String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::{NonInteractive, Scripted};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    /// Write a minimal EPUB with the given dc:title and dc:creator elements
+    fn write_epub(path: &Path, metadata: &str) -> zip::result::ZipResult<()> {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path)?);
+        let options = SimpleFileOptions::default();
+        zip.start_file("mimetype", options)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", options)?;
+        zip.write_all(br#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#)?;
+        zip.start_file("content.opf", options)?;
+        write!(
+            zip,
+            r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/">{metadata}<dc:identifier id="id">urn:isbn:9780552137034</dc:identifier></metadata><manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest><spine><itemref idref="c1"/></spine></package>"#
+        )?;
+        zip.start_file("c1.xhtml", options)?;
+        zip.write_all(b"<html><body></body></html>")?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_author() -> zip::result::ZipResult<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_epub_test_{}.epub",
+            rand::random::<u64>()
+        ));
+        write_epub(
+            &path,
+            "<dc:title>Good Omens</dc:title><dc:creator>Terry Pratchett</dc:creator><dc:creator>Neil Gaiman</dc:creator>",
+        )?;
+
+        let aliases = AuthorAliases::default();
+        let non_interactive = NonInteractive::default();
+        let metadata = parse_epub(&path, &non_interactive, &aliases).unwrap();
+        assert_eq!(metadata.main_author, "");
+        let declined = non_interactive.take_declined();
+        assert_eq!(
+            declined[0].options,
+            vec![String::from("Neil Gaiman"), String::from("Terry Pratchett")]
+        );
+
+        let scripted = Scripted::new(&["1"]);
+        let metadata = parse_epub(&path, &scripted, &aliases).unwrap();
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+        assert_eq!(scripted.questions_asked(), 1);
+        // The answer is remembered for the next book with the same authors
+        let metadata = parse_epub(&path, &scripted, &aliases).unwrap();
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+        assert_eq!(scripted.questions_asked(), 1);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_title_is_asked() -> zip::result::ZipResult<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_epub_test_{}.epub",
+            rand::random::<u64>()
+        ));
+        write_epub(
+            &path,
+            "<dc:creator>Terry Pratchett</dc:creator><dc:creator>Neil Gaiman</dc:creator>",
+        )?;
+
+        // The author is picked, then the title is still asked for rather than the file dropped
+        let scripted = Scripted::new(&["1", "Good Omens"]);
+        let metadata =
+            crate::parsers::parse_file(&path, &scripted, &AuthorAliases::default()).unwrap();
+        assert_eq!(metadata.title, "Good Omens");
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+        assert_eq!(scripted.questions_asked(), 2);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_aliases_and_answers_kept_as_given() -> zip::result::ZipResult<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_epub_test_{}.epub",
+            rand::random::<u64>()
+        ));
+        write_epub(
+            &path,
+            "<dc:title>Good Omens</dc:title><dc:creator>PRATCHETT, TERRY</dc:creator>",
+        )?;
+        let parse = |scripted: &Scripted, aliases: &AuthorAliases| {
+            crate::parsers::parse_file(&path, scripted, aliases)
+                .unwrap()
+                .main_author
+        };
+
+        // Names read from the file are tidied up
+        let aliases = AuthorAliases::default();
+        assert_eq!(parse(&Scripted::new(&[]), &aliases), "Terry Pratchett");
+        // but an alias is the name to use, as it is
+        aliases.remember(&[String::from("PRATCHETT, TERRY")], "Pratchett, Terry");
+        assert_eq!(parse(&Scripted::new(&[]), &aliases), "Pratchett, Terry");
+
+        // and so is a typed answer
+        write_epub(
+            &path,
+            "<dc:title>Good Omens</dc:title><dc:creator>Terry Pratchett</dc:creator><dc:creator>Neil Gaiman</dc:creator>",
+        )?;
+        let scripted = Scripted::new(&["other", "Simon and Schuster"]);
+        assert_eq!(
+            parse(&scripted, &AuthorAliases::default()),
+            "Simon and Schuster"
+        );
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::{fs, io::Read, path::Path};
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::{
+    names::normalise_authors,
+    parsers::{metadata::FileMetadata, misc::clean_series_index},
+    prompt::Interactor,
+};
+
+/// Decode FB2 bytes using the encoding named in the XML declaration, as many older books are windows-1251
+fn decode(bytes: &[u8]) -> String {
+    let declaration_end = bytes.iter().position(|&b| b == b'>').unwrap_or(bytes.len());
+    let declaration = String::from_utf8_lossy(&bytes[..declaration_end]);
+    let encoding = declaration
+        .split("encoding=")
+        .nth(1)
+        .and_then(|rest| {
+            rest.trim_start_matches(['"', '\''])
+                .split(['"', '\''])
+                .next()
+        })
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+/// Read the book from a .fb2 file, or the first .fb2 inside a .fb2.zip
+fn read_fb2(file_path: &Path) -> Result<String, String> {
+    let is_zip = file_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if !is_zip {
+        return fs::read(file_path)
+            .map(|bytes| decode(&bytes))
+            .map_err(|e| e.to_string());
+    }
+
+    let file = fs::File::open(file_path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let name = archive
+        .file_names()
+        .find(|name| name.to_lowercase().ends_with(".fb2"))
+        .map(str::to_owned)
+        .ok_or("no .fb2 file in archive")?;
+    let mut bytes = Vec::new();
+    archive
+        .by_name(&name)
+        .map_err(|e| e.to_string())?
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(decode(&bytes))
+}
+
+/// Join an <author> element's name parts in reading order, falling back to the nickname
+fn author_name(author: roxmltree::Node) -> Option<String> {
+    let part = |name: &str| {
+        author
+            .children()
+            .find(|n| n.tag_name().name() == name)
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+    };
+    let name: Vec<&str> = ["first-name", "middle-name", "last-name"]
+        .into_iter()
+        .filter_map(part)
+        .collect();
+    if name.is_empty() {
+        part("nickname").map(str::to_owned)
+    } else {
+        Some(name.join(" "))
+    }
+}
+
+pub fn parse_fb2(file_path: &Path, interactor: &dyn Interactor) -> Result<FileMetadata, String> {
+    let xml = match read_fb2(file_path) {
+        Ok(xml) => xml,
+        Err(e) => {
+            println!("Failed to read FB2 file: {e}");
+            return Err(e);
+        }
+    };
+    let doc = match roxmltree::Document::parse(&xml) {
+        Ok(doc) => doc,
+        Err(e) => {
+            println!("Failed to parse FB2 file: {e:?}");
+            return Err(e.to_string());
+        }
+    };
+
+    let title_info = doc
+        .descendants()
+        .find(|n| n.tag_name().name() == "title-info")
+        .ok_or("no title-info in FB2 description")?;
+
+    let title = title_info
+        .children()
+        .find(|n| n.tag_name().name() == "book-title")
+        .and_then(|n| n.text())
+        .map(str::to_owned)
+        .unwrap_or_default();
+    let author = title_info
+        .children()
+        .filter(|n| n.tag_name().name() == "author")
+        .find_map(author_name);
+
+    let selected_author = author
+        .map(|author| normalise_authors(&author))
+        .unwrap_or_else(|| {
+            interactor
+                .ask_text(&format!(
+                    "No author found for {}, please enter one:",
+                    file_path.display()
+                ))
+                .unwrap_or_default()
+        });
+
+    // <sequence name="..." number="..."/>, the first one is the main series
+    let sequence = title_info
+        .children()
+        .find(|n| n.tag_name().name() == "sequence");
+    let series = sequence
+        .and_then(|n| n.attribute("name"))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned);
+    let series_index = sequence
+        .filter(|_| series.is_some())
+        .and_then(|n| n.attribute("number"))
+        .and_then(clean_series_index);
+
+    Ok(FileMetadata {
+        title: title.trim().to_owned(),
+        main_author: selected_author.trim().to_owned(),
+        series,
+        series_index,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Scripted;
+    use std::io::Write;
+
+    /// An FB2 book with the given encoding in its XML declaration and the given title-info
+    fn fb2(encoding: &str, title_info: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="{encoding}"?><FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0"><description><title-info>{title_info}</title-info></description><body><section><p>...</p></section></body></FictionBook>"#
+        )
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "ebook_organiser_fb2_test_{}_{name}",
+            rand::random::<u64>()
+        ))
+    }
+
+    #[test]
+    fn test_windows_1251() -> std::io::Result<()> {
+        let xml = fb2(
+            "windows-1251",
+            r#"<author><last-name>Толстой</last-name><first-name>Лев</first-name><middle-name>Николаевич</middle-name></author><book-title>Война и мир</book-title><sequence name="Эпопея" number="1.0"/>"#,
+        );
+        let (bytes, _, unmappable) = encoding_rs::WINDOWS_1251.encode(&xml);
+        assert!(!unmappable);
+        let path = temp_path("war.fb2");
+        fs::write(&path, &bytes)?;
+
+        let metadata = parse_fb2(&path, &Scripted::new(&[])).unwrap();
+        assert_eq!(metadata.title, "Война и мир");
+        // Name parts are put in reading order, whatever order they are given in
+        assert_eq!(metadata.main_author, "Лев Николаевич Толстой");
+        assert_eq!(metadata.series.as_deref(), Some("Эпопея"));
+        assert_eq!(metadata.series_index.as_deref(), Some("1"));
+
+        fs::remove_file(&path)
+    }
+
+    #[test]
+    fn test_zipped() -> zip::result::ZipResult<()> {
+        let xml = fb2(
+            "utf-8",
+            r#"<author><nickname>Lewis Carroll</nickname></author><author><first-name>John</first-name><last-name>Tenniel</last-name></author><book-title>Alice in Wonderland</book-title><sequence name=" "/>"#,
+        );
+        let path = temp_path("Alice.FB2.ZIP");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path)?);
+        zip.start_file("cover.jpg", zip::write::SimpleFileOptions::default())?;
+        zip.start_file("Alice.FB2", zip::write::SimpleFileOptions::default())?;
+        zip.write_all(xml.as_bytes())?;
+        zip.finish()?;
+
+        let metadata = parse_fb2(&path, &Scripted::new(&[])).unwrap();
+        assert_eq!(metadata.title, "Alice in Wonderland");
+        // The first author is the main one, by nickname if that is all there is
+        assert_eq!(metadata.main_author, "Lewis Carroll");
+        assert_eq!(metadata.series, None);
+        assert_eq!(metadata.series_index, None);
+        // The extension is recognised whatever its case
+        let aliases = crate::aliases::AuthorAliases::default();
+        let parsed = crate::parsers::parse_file(&path, &Scripted::new(&[]), &aliases);
+        assert_eq!(parsed, Some(metadata));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,43 +1,200 @@
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 
 use crate::{
-    parsers::{audiobook::parse_audiobook, epub::parse_epub, mobi::parse_mobi},
-    prompt::prompt,
+    aliases::AuthorAliases,
+    parsers::{
+        audiobook::parse_audiobook, audiobook_folder::parse_audiobook_folder, comic::parse_comic,
+        epub::parse_epub, fb2::parse_fb2, misc::file_extension, mobi::parse_mobi, mp3::parse_mp3,
+        pdf::parse_pdf, vorbis::parse_vorbis,
+    },
+    prompt::Interactor,
 };
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Extensions of the files that are sorted into the ebook library
+pub const EBOOK_EXTENSIONS: &[&str] = &["epub", "mobi", "azw", "azw3", "pdf", "fb2", "fb2.zip"];
+/// Extensions of the files that are sorted into the audiobook library
+pub const AUDIOBOOK_EXTENSIONS: &[&str] = &["m4b", "m4a", "mp3", "opus", "ogg", "flac"];
+/// Extensions of the files that are sorted into the comic library
+pub const COMIC_EXTENSIONS: &[&str] = &["cbz", "cbr", "cb7"];
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct FileMetadata {
     pub title: String,
     pub main_author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+    /// Position in the series, kept as text as it can be "1.5" or "012"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_index: Option<String>,
+    /// ISBN-10 or ISBN-13, digits only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher: Option<String>,
+    /// Language code as given in the file, such as "en" or "en-GB"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Publication date as given in the file, usually starting with the year
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Subjects or genres, most relevant first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+    /// Audiobook narrator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub narrator: Option<String>,
+    /// Audiobook running time in whole seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chapter_count: Option<usize>,
+}
+
+impl FileMetadata {
+    /// The four digit year at the start of the publication date
+    pub fn year(&self) -> Option<&str> {
+        let year = self.date.as_deref()?.trim().get(..4)?;
+        year.chars().all(|c| c.is_ascii_digit()).then_some(year)
+    }
+
+    /// Running time such as "10h 05m", which is safe to use in a file name
+    pub fn duration(&self) -> Option<String> {
+        let minutes = self.duration_seconds? / 60;
+        Some(format!("{}h {:02}m", minutes / 60, minutes % 60))
+    }
+
+    /// The values available to the format template, by variable name (missing values are empty)
+    pub fn template_values(&self) -> Vec<(&'static str, String)> {
+        fn optional(value: &Option<String>) -> &str {
+            value.as_deref().unwrap_or_default()
+        }
+        let values: Vec<(&'static str, &str)> = vec![
+            ("title", &self.title),
+            ("author", &self.main_author),
+            ("series", optional(&self.series)),
+            ("series_index", optional(&self.series_index)),
+            ("isbn", optional(&self.isbn)),
+            ("asin", optional(&self.asin)),
+            ("uuid", optional(&self.uuid)),
+            ("publisher", optional(&self.publisher)),
+            ("language", optional(&self.language)),
+            ("date", optional(&self.date)),
+            ("year", self.year().unwrap_or_default()),
+            (
+                "genre",
+                self.subjects
+                    .first()
+                    .map(String::as_str)
+                    .unwrap_or_default(),
+            ),
+            ("narrator", optional(&self.narrator)),
+        ];
+        let mut values: Vec<(&'static str, String)> = values
+            .into_iter()
+            .map(|(name, value)| (name, value.to_owned()))
+            .collect();
+        values.push(("duration", self.duration().unwrap_or_default()));
+        values.push((
+            "chapters",
+            self.chapter_count
+                .map(|count| count.to_string())
+                .unwrap_or_default(),
+        ));
+        values
+    }
 }
 
-pub fn parse_file(file_path: &Path) -> Option<FileMetadata> {
+/// Read a file's metadata, asking `interactor` about anything missing or ambiguous and
+/// checking `aliases` for authors settled before.
+///
+/// The parsers tidy up the author names they read from the file, but aliases and typed
+/// answers are used exactly as given.
+pub fn parse_file(
+    file_path: &Path,
+    interactor: &dyn Interactor,
+    aliases: &AuthorAliases,
+) -> Option<FileMetadata> {
     // If file is an epub, we can parse it
-    let ext = file_path
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or_default();
-
-    let metadata: Option<FileMetadata> = if ext == "epub" {
-        parse_epub(file_path).ok()
-    } else if ext == "mobi" {
-        parse_mobi(file_path).ok()
+    let ext = file_extension(file_path).unwrap_or_default();
+    let ext = ext.as_str();
+    let asked = interactor.questions_asked();
+
+    let metadata: Option<FileMetadata> = if file_path.is_dir() {
+        parse_audiobook_folder(file_path, interactor).ok()
+    } else if ext == "epub" {
+        parse_epub(file_path, interactor, aliases).ok()
+    } else if ext == "mobi" || ext == "azw" || ext == "azw3" {
+        parse_mobi(file_path, interactor, aliases).ok()
+    } else if ext == "fb2" || ext == "fb2.zip" {
+        parse_fb2(file_path, interactor).ok()
+    } else if ext == "pdf" {
+        parse_pdf(file_path, interactor).ok()
     } else if ext == "m4a" || ext == "m4b" {
-        parse_audiobook(file_path).ok()
+        parse_audiobook(file_path, interactor, aliases).ok()
+    } else if ext == "mp3" {
+        parse_mp3(file_path, interactor).ok()
+    } else if ext == "opus" || ext == "ogg" || ext == "flac" {
+        parse_vorbis(file_path, interactor).ok()
+    } else if COMIC_EXTENSIONS.contains(&ext) {
+        parse_comic(file_path, interactor).ok()
     } else {
         None
     };
-    if let Some(meta) = metadata {
-        if !meta.title.is_empty() && !meta.main_author.is_empty() {
-            return Some(meta);
-        }
+    let mut meta = metadata.unwrap_or_default();
+    if !meta.title.is_empty() && !meta.main_author.is_empty() {
+        return Some(meta);
+    }
+    // In a non-interactive run the parser's own question, with its options, is the one to
+    // review; asking more would only bury it
+    if !interactor.is_interactive() && interactor.questions_asked() > asked {
+        return None;
     }
     println!("Failed to parse file metadata for: {file_path:?} ",);
-    let title = prompt("Enter title");
-    let main_author = prompt("Enter main author");
-    if title.is_empty() || main_author.is_empty() {
+    // Keep whatever the parser found or was told, and only ask for the rest
+    if meta.title.is_empty() {
+        meta.title = interactor.ask_text("Enter title")?;
+    }
+    if meta.main_author.is_empty() {
+        meta.main_author = interactor.ask_text("Enter main author")?;
+    }
+    if meta.title.is_empty() || meta.main_author.is_empty() {
         println!("Title or main author cannot be empty.");
         return None;
     }
-    Some(FileMetadata { title, main_author })
+    Some(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_values() {
+        let metadata = FileMetadata {
+            title: String::from("The Hobbit"),
+            main_author: String::from("J. R. R. Tolkien"),
+            date: Some(String::from("1937-09-21")),
+            narrator: Some(String::from("Andy Serkis")),
+            duration_seconds: Some(10 * 3600 + 5 * 60 + 59),
+            chapter_count: Some(19),
+            ..Default::default()
+        };
+        let values = metadata.template_values();
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(value("year"), Some("1937"));
+        assert_eq!(value("narrator"), Some("Andy Serkis"));
+        assert_eq!(value("duration"), Some("10h 05m"));
+        assert_eq!(value("chapters"), Some("19"));
+        assert_eq!(value("series"), Some(""));
+    }
 }
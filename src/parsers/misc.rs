@@ -1,9 +1,179 @@
-pub fn flip_comma_split(val: String) -> String {
-    if val.contains(',') {
-        let mut parts: Vec<&str> = val.split(',').collect();
-        parts.reverse();
-        parts.join(" ")
-    } else {
-        val
+use std::path::Path;
+
+/// Extensions made of two parts, where `Path::extension` would only see the last one
+const DOUBLE_EXTENSIONS: &[&str] = &["fb2.zip"];
+
+/// Get the extension of a file in lower case, keeping double extensions such as `fb2.zip` whole
+pub fn file_extension(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    for double in DOUBLE_EXTENSIONS {
+        if file_name.ends_with(&format!(".{double}")) {
+            return Some(double.to_string());
+        }
+    }
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// Strip a scheme prefix such as "urn:isbn:" or "ISBN " from an identifier
+fn strip_scheme<'a>(value: &'a str, scheme: &str) -> &'a str {
+    let value = value.trim();
+    for prefix in [
+        format!("urn:{scheme}:"),
+        format!("{scheme}:"),
+        format!("{scheme} "),
+    ] {
+        if value.len() > prefix.len()
+            && value
+                .get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(&prefix))
+        {
+            return value[prefix.len()..].trim();
+        }
+    }
+    value
+}
+
+/// Read an ISBN-10 or ISBN-13 from an identifier like "urn:isbn:978-0-261-10357-3"
+pub fn parse_isbn(value: &str) -> Option<String> {
+    let isbn: String = strip_scheme(value, "isbn")
+        .chars()
+        .filter(|c| *c != '-' && *c != ' ')
+        .collect::<String>()
+        .to_uppercase();
+    let valid = isbn.is_ascii()
+        && match isbn.len() {
+            13 => isbn.chars().all(|c| c.is_ascii_digit()),
+            10 => {
+                isbn[..9].chars().all(|c| c.is_ascii_digit())
+                    && isbn[9..].chars().all(|c| c.is_ascii_digit() || c == 'X')
+            }
+            _ => false,
+        };
+    valid.then_some(isbn)
+}
+
+/// Read a UUID from an identifier like "urn:uuid:..."
+pub fn parse_uuid(value: &str) -> Option<String> {
+    let uuid = strip_scheme(value, "uuid").to_lowercase();
+    let valid = uuid.len() == 36
+        && uuid.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+    valid.then_some(uuid)
+}
+
+/// Read an Amazon ASIN, ten letters and digits such as "B00DKBXZ0S"
+pub fn parse_asin(value: &str) -> Option<String> {
+    let asin = strip_scheme(value, "asin").to_uppercase();
+    (asin.len() == 10 && asin.chars().all(|c| c.is_ascii_alphanumeric())).then_some(asin)
+}
+
+/// Tidy up a series position, dropping the ".0" calibre adds to whole numbers
+pub fn clean_series_index(index: &str) -> Option<String> {
+    let index = index.trim().trim_start_matches('#');
+    if index.is_empty() {
+        return None;
+    }
+    match index.split_once('.') {
+        Some((whole, fraction)) if fraction.chars().all(|c| c == '0') => Some(whole.to_owned()),
+        _ => Some(index.to_owned()),
+    }
+}
+
+/// Split a store style title such as "The Way of Kings (The Stormlight Archive Book 1)" into the
+/// title, series and series position
+pub fn split_series_suffix(title: &str) -> Option<(String, String, String)> {
+    let title = title.trim();
+    let (name, suffix) = title.strip_suffix(')')?.rsplit_once(" (")?;
+    let (series, index) = suffix.rsplit_once(' ')?;
+    let index = clean_series_index(index)?;
+    if !index.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let series = series
+        .trim_end_matches(',')
+        .trim_end_matches(" Book")
+        .trim_end_matches(" Vol.")
+        .trim_end_matches(',')
+        .trim();
+    if name.trim().is_empty() || series.is_empty() {
+        return None;
+    }
+    Some((name.trim().to_owned(), series.to_owned(), index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_extension() {
+        assert_eq!(
+            file_extension(Path::new("/in/War and Peace.fb2.zip")).as_deref(),
+            Some("fb2.zip")
+        );
+        assert_eq!(
+            file_extension(Path::new("/in/Book.FB2.ZIP")).as_deref(),
+            Some("fb2.zip")
+        );
+        assert_eq!(
+            file_extension(Path::new("/in/Book.EPUB")).as_deref(),
+            Some("epub")
+        );
+        assert_eq!(
+            file_extension(Path::new("/in/archive.zip")).as_deref(),
+            Some("zip")
+        );
+        assert_eq!(
+            file_extension(Path::new("/in/book.epub")).as_deref(),
+            Some("epub")
+        );
+        assert_eq!(file_extension(Path::new("/in/README")), None);
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            parse_isbn("urn:isbn:978-0-261-10357-3").as_deref(),
+            Some("9780261103573")
+        );
+        assert_eq!(
+            parse_isbn("ISBN 0-261-10357-x").as_deref(),
+            Some("026110357X")
+        );
+        assert_eq!(parse_isbn("calibre:1234"), None);
+        assert_eq!(parse_isbn("Идентификатор"), None);
+        assert_eq!(parse_isbn("12345678é"), None);
+        assert_eq!(strip_scheme("Идентификатор", "isbn"), "Идентификатор");
+        assert_eq!(parse_asin("識別子"), None);
+        assert_eq!(
+            parse_uuid("urn:uuid:0B5D4E1F-9A7C-4E5B-8C3D-2F1A6B7C8D9E").as_deref(),
+            Some("0b5d4e1f-9a7c-4e5b-8c3d-2f1a6b7c8d9e")
+        );
+        assert_eq!(parse_uuid("9780261103573"), None);
+        assert_eq!(parse_asin("b00dkbxz0s").as_deref(), Some("B00DKBXZ0S"));
+    }
+
+    #[test]
+    fn test_series_helpers() {
+        assert_eq!(clean_series_index("2.0").as_deref(), Some("2"));
+        assert_eq!(clean_series_index("1.5").as_deref(), Some("1.5"));
+        assert_eq!(clean_series_index(" "), None);
+        assert_eq!(
+            split_series_suffix("The Way of Kings (The Stormlight Archive Book 1)"),
+            Some((
+                String::from("The Way of Kings"),
+                String::from("The Stormlight Archive"),
+                String::from("1")
+            ))
+        );
+        assert_eq!(
+            split_series_suffix("Dune (Dune Chronicles, #1)").map(|s| s.1),
+            Some(String::from("Dune Chronicles"))
+        );
+        assert_eq!(split_series_suffix("Nineteen Eighty-Four (Penguin)"), None);
     }
 }
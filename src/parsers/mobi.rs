@@ -1,31 +1,129 @@
 use std::path::Path;
 
-use mobi::Mobi;
+use mobi::{Mobi, MobiMetadata, headers::ExthRecord};
 
-use crate::{parsers::metadata::FileMetadata, prompt::prompt};
+use crate::{
+    aliases::AuthorAliases,
+    names::normalise_authors,
+    parsers::{
+        metadata::FileMetadata,
+        misc::{parse_asin, parse_isbn, parse_uuid, split_series_suffix},
+    },
+    prompt::Interactor,
+};
 
-pub fn parse_mobi(file_path: &Path) -> Result<FileMetadata, String> {
-    // Open the MOBI file and parse its metadata
+/// EXTH record holding the index of the first record of the KF8 part of a combined MOBI/KF8 file
+const EXTH_KF8_BOUNDARY: u32 = 121;
+
+/// Read the MOBI/EXTH headers of the KF8 section of a combined file, if it has one.
+///
+/// The KF8 part starts with its own header record laid out like record 0, so it is wrapped in
+/// a minimal single record PDB to let the mobi crate parse it the same way as the main header.
+fn kf8_metadata(mobi: &Mobi) -> Option<MobiMetadata> {
+    let boundary = mobi.metadata.exth_record_at(EXTH_KF8_BOUNDARY)?.first()?;
+    let boundary = u32::from_be_bytes(boundary.as_slice().try_into().ok()?);
+    if boundary == u32::MAX {
+        return None;
+    }
+    let records = mobi.raw_records();
+    let kf8_header = records.records().get(boundary as usize)?;
+
+    // PDB header: 32 byte name, 28 bytes of attributes/dates/ids, type, creator, 8 more bytes, record count
+    let mut pdb = vec![0u8; 60];
+    pdb.extend_from_slice(b"BOOKMOBI");
+    pdb.extend_from_slice(&[0u8; 8]);
+    pdb.extend_from_slice(&1u16.to_be_bytes());
+    // Single record list entry (offset and id), then the two padding bytes
+    let record_offset = (pdb.len() + 8 + 2) as u32;
+    pdb.extend_from_slice(&record_offset.to_be_bytes());
+    pdb.extend_from_slice(&[0u8; 4 + 2]);
+    pdb.extend_from_slice(kf8_header.content);
+
+    MobiMetadata::new(&pdb).ok()
+}
+
+/// EXTH record holding the language code, such as "en-GB"
+const EXTH_LANGUAGE: u32 = 524;
+
+/// Read the text of every EXTH record at `position`, from the first header that has any
+fn exth_texts(headers: &[&MobiMetadata], position: u32) -> Vec<String> {
+    headers
+        .iter()
+        .filter_map(|h| h.exth_record_at(position))
+        .map(|records| {
+            records
+                .iter()
+                .map(|r| String::from_utf8_lossy(r).trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .find(|texts| !texts.is_empty())
+        .unwrap_or_default()
+}
+
+fn exth_text(headers: &[&MobiMetadata], record: ExthRecord) -> Option<String> {
+    exth_texts(headers, record.position()).into_iter().next()
+}
+
+pub fn parse_mobi(
+    file_path: &Path,
+    interactor: &dyn Interactor,
+    aliases: &AuthorAliases,
+) -> Result<FileMetadata, String> {
+    // Open the MOBI/AZW file and parse its metadata
     match Mobi::from_path(file_path) {
         Ok(mobi) => {
+            // Combined files carry a second header for KF8 readers, which is preferred when present
+            let kf8 = kf8_metadata(&mobi);
+            let headers: Vec<&MobiMetadata> = kf8.iter().chain([&mobi.metadata]).collect();
+
             // Extract title from mobi metadata
-            let title = mobi.title();
-            let author = mobi.author();
-            let contributors = mobi.contributor();
+            let title = headers
+                .iter()
+                .map(|h| h.title())
+                .find(|t| !t.trim().is_empty())
+                .unwrap_or_default();
+            let author = headers.iter().find_map(|h| h.author());
+            let contributors = headers.iter().find_map(|h| h.contributor());
             let selected_author = if let Some(author) = author {
-                author
+                aliases
+                    .canonical(std::slice::from_ref(&author))
+                    .unwrap_or_else(|| normalise_authors(&author))
             } else if let Some(contributor) = contributors {
-                contributor
+                aliases
+                    .canonical(std::slice::from_ref(&contributor))
+                    .unwrap_or_else(|| normalise_authors(&contributor))
             } else {
-                prompt(&format!(
-                    "No author found for {}, please enter one:",
-                    file_path.display()
-                ))
+                interactor
+                    .ask_text(&format!(
+                        "No author found for {}, please enter one:",
+                        file_path.display()
+                    ))
+                    .unwrap_or_default()
+            };
+
+            // MOBI has no series record, but store bought books carry it in the updated title
+            // (EXTH 503), as in "The Way of Kings (The Stormlight Archive Book 1)"
+            let (title, series, series_index) = match split_series_suffix(&title) {
+                Some((title, series, index)) => (title, Some(series), Some(index)),
+                None => (title, None, None),
             };
 
             Ok(FileMetadata {
                 title: title.trim().to_owned(),
                 main_author: selected_author.trim().to_owned(),
+                series,
+                series_index,
+                isbn: exth_text(&headers, ExthRecord::Isbn).and_then(|i| parse_isbn(&i)),
+                asin: exth_text(&headers, ExthRecord::Asin).and_then(|a| parse_asin(&a)),
+                // calibre stores its book id in the source record as "calibre:<uuid>"
+                uuid: exth_text(&headers, ExthRecord::Source)
+                    .and_then(|s| parse_uuid(s.trim_start_matches("calibre:"))),
+                publisher: exth_text(&headers, ExthRecord::Publisher),
+                language: exth_texts(&headers, EXTH_LANGUAGE).into_iter().next(),
+                date: exth_text(&headers, ExthRecord::PublishDate),
+                subjects: exth_texts(&headers, ExthRecord::Subject.position()),
+                ..Default::default()
             })
         }
         Err(e) => {
@@ -34,3 +132,117 @@ pub fn parse_mobi(file_path: &Path) -> Result<FileMetadata, String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::NonInteractive;
+
+    /// An EXTH header holding the given records, padded to a multiple of four bytes
+    fn exth(records: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, data) in records {
+            body.extend(kind.to_be_bytes());
+            body.extend((data.len() as u32 + 8).to_be_bytes());
+            body.extend_from_slice(data);
+        }
+        let mut header = b"EXTH".to_vec();
+        header.extend((body.len() as u32 + 12).to_be_bytes());
+        header.extend((records.len() as u32).to_be_bytes());
+        header.extend(body);
+        header.resize(header.len().next_multiple_of(4), 0);
+        header
+    }
+
+    /// A header record, laid out the same for record 0 and the start of the KF8 part: the
+    /// PalmDOC header, the MOBI header, the EXTH header and then the full name
+    fn header_record(name: &[u8], records: &[(u32, &[u8])], version: u32) -> Vec<u8> {
+        const MOBI_HEADER_LENGTH: usize = 232;
+        // No compression, one 4096 byte text record, no encryption
+        let mut record = Vec::new();
+        record.extend(1u16.to_be_bytes());
+        record.extend(0u16.to_be_bytes());
+        record.extend(4u32.to_be_bytes());
+        for value in [1u16, 4096, 0, 0] {
+            record.extend(value.to_be_bytes());
+        }
+        let exth = exth(records);
+        let name_offset = record.len() + MOBI_HEADER_LENGTH + exth.len();
+
+        let mut mobi = vec![0u8; MOBI_HEADER_LENGTH];
+        mobi[..4].copy_from_slice(b"MOBI");
+        let mut put = |offset: usize, value: usize| {
+            mobi[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes());
+        };
+        put(4, MOBI_HEADER_LENGTH);
+        // Mobipocket book, UTF-8
+        put(8, 2);
+        put(12, 65001);
+        put(20, version as usize);
+        put(64, 2);
+        put(68, name_offset);
+        put(72, name.len());
+        put(88, version as usize);
+        // Has an EXTH header
+        put(112, 0x40);
+
+        record.extend(mobi);
+        record.extend(exth);
+        record.extend_from_slice(name);
+        record.extend([0, 0]);
+        record
+    }
+
+    /// A PDB file holding the given records
+    fn pdb(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = b"kindle".to_vec();
+        file.resize(32 + 28, 0);
+        file.extend(b"BOOKMOBI");
+        file.extend([0u8; 8]);
+        file.extend((records.len() as u16).to_be_bytes());
+        let mut offset = file.len() + 8 * records.len() + 2;
+        for (id, record) in records.iter().enumerate() {
+            file.extend((offset as u32).to_be_bytes());
+            file.extend((id as u32).to_be_bytes());
+            offset += record.len();
+        }
+        file.extend([0, 0]);
+        for record in records {
+            file.extend(record);
+        }
+        file
+    }
+
+    #[test]
+    fn test_kf8_header_wins() -> std::io::Result<()> {
+        let boundary = 3u32.to_be_bytes();
+        let mobi7 = header_record(
+            b"Old Name",
+            &[(EXTH_KF8_BOUNDARY, &boundary), (100, b"Old Author")],
+            6,
+        );
+        let kf8 = header_record(
+            b"KF8 Name",
+            &[(100, b"Ann Leckie"), (503, b"Ancillary Justice")],
+            8,
+        );
+        let file = pdb(&[mobi7, b"text".to_vec(), b"BOUNDARY".to_vec(), kf8]);
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_mobi_test_{}.azw3",
+            rand::random::<u64>()
+        ));
+        std::fs::write(&path, file)?;
+
+        let mobi = Mobi::from_path(&path).unwrap();
+        assert_eq!(mobi.metadata.author().as_deref(), Some("Old Author"));
+        let kf8 = kf8_metadata(&mobi).unwrap();
+        assert_eq!(kf8.author().as_deref(), Some("Ann Leckie"));
+
+        let metadata =
+            parse_mobi(&path, &NonInteractive::default(), &AuthorAliases::default()).unwrap();
+        assert_eq!(metadata.title, "Ancillary Justice");
+        assert_eq!(metadata.main_author, "Ann Leckie");
+
+        std::fs::remove_file(&path)
+    }
+}
@@ -1,7 +1,14 @@
 mod audiobook;
+pub mod audiobook_folder;
+pub mod comic;
 pub mod epub;
+pub mod fb2;
 pub mod metadata;
 mod misc;
 pub mod mobi;
+pub mod mp3;
+pub mod pdf;
+pub mod vorbis;
 
 pub use metadata::*;
+pub use misc::file_extension;
@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use encoding_rs::{UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use id3::{Content, Tag, TagLike};
+
+use crate::{
+    names::normalise_authors,
+    parsers::{audiobook_folder::AudioTags, metadata::FileMetadata, misc::clean_series_index},
+    prompt::Interactor,
+};
+
+/// Decode a text frame the id3 crate doesn't know, such as iTunes' MVNM and MVIN, from its
+/// encoding byte and text
+fn decode_text(data: &[u8]) -> Option<String> {
+    let (&encoding, text) = data.split_first()?;
+    let (text, _) = match encoding {
+        0 => WINDOWS_1252.decode_without_bom_handling(text),
+        // UTF-16 starts with a byte order mark
+        1 => UTF_16LE.decode_with_bom_removal(text),
+        2 => UTF_16BE.decode_without_bom_handling(text),
+        3 => UTF_8.decode_without_bom_handling(text),
+        _ => return None,
+    };
+    // ID3v2.4 separates several values with a null, the first is the one wanted
+    text.split('\0').next().map(str::to_owned)
+}
+
+/// Read a text frame, preferring the ID3v2 tag and falling back to the ID3v1 tag
+fn frame_text(tags: &[Tag], frame_id: &str) -> Option<String> {
+    tags.iter()
+        .filter_map(|tag| {
+            tag.get(frame_id).and_then(|frame| match frame.content() {
+                Content::Unknown(unknown) => decode_text(&unknown.data),
+                content => content.text().map(str::to_owned),
+            })
+        })
+        .map(|text| text.trim().to_owned())
+        .find(|text| !text.is_empty())
+}
+
+/// Read a user defined (TXXX) text frame by its description
+fn extended_text(tags: &[Tag], description: &str) -> Option<String> {
+    tags.iter()
+        .flat_map(|tag| tag.extended_texts())
+        .filter(|text| text.description.eq_ignore_ascii_case(description))
+        .map(|text| text.value.trim())
+        .find(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+/// Read the series from TXXX SERIES and SERIES-PART frames, or the movement frames
+fn series(tags: &[Tag]) -> (Option<String>, Option<String>) {
+    if let Some(series) = extended_text(tags, "SERIES") {
+        let index = extended_text(tags, "SERIES-PART").and_then(|i| clean_series_index(&i));
+        return (Some(series), index);
+    }
+    match frame_text(tags, "MVNM") {
+        Some(series) => (
+            Some(series),
+            frame_text(tags, "MVIN").and_then(|i| clean_series_index(i.split('/').next()?)),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Read the ID3v2 and ID3v1 tags, in that order of preference
+fn read_tags(file_path: &Path) -> Result<Vec<Tag>, id3::Error> {
+    // An ID3v1 tag only has room for 30 character fields, so it is only used to fill gaps
+    let v2 = Tag::read_from_path(file_path);
+    let v1 = id3::v1::Tag::read_from_path(file_path).map(Tag::from);
+    match (v2, v1) {
+        (Err(e), Err(_)) => Err(e),
+        (v2, v1) => Ok([v2.ok(), v1.ok()].into_iter().flatten().collect()),
+    }
+}
+
+/// Read the album and author tags without prompting
+pub fn read_mp3_tags(file_path: &Path) -> Option<AudioTags> {
+    let tags = read_tags(file_path).ok()?;
+    let author = ["TPE1", "TPE2", "TCOM"]
+        .iter()
+        .find_map(|frame_id| frame_text(&tags, frame_id));
+    Some(AudioTags::new(
+        frame_text(&tags, "TALB").as_deref(),
+        author.as_deref(),
+    ))
+}
+
+pub fn parse_mp3(file_path: &Path, interactor: &dyn Interactor) -> Result<FileMetadata, String> {
+    let tags = match read_tags(file_path) {
+        Ok(tags) => tags,
+        Err(e) => {
+            println!("Failed to parse mp3 file: {e:?}");
+            return Err(e.to_string());
+        }
+    };
+
+    // Same as MP4 audiobooks, fall back to the album when there is no title
+    let title = frame_text(&tags, "TIT2")
+        .or_else(|| frame_text(&tags, "TALB"))
+        .unwrap_or_else(|| {
+            interactor
+                .ask_text(&format!(
+                    "No title found for {}, please enter one:",
+                    file_path.display()
+                ))
+                .unwrap_or_default()
+        });
+
+    let author = frame_text(&tags, "TPE1");
+    let album_artist = frame_text(&tags, "TPE2");
+    let composer = frame_text(&tags, "TCOM");
+    let selected_author = if let Some(author) = author {
+        normalise_authors(&author)
+    } else if let Some(author) = album_artist {
+        normalise_authors(&author)
+    } else if let Some(author) = composer {
+        normalise_authors(&author)
+    } else {
+        interactor
+            .ask_text(&format!(
+                "No author found for {}, please enter one:",
+                file_path.display()
+            ))
+            .unwrap_or_default()
+    };
+
+    let (series, series_index) = series(&tags);
+
+    Ok(FileMetadata {
+        title: title.trim().to_owned(),
+        main_author: selected_author.trim().to_owned(),
+        series,
+        series_index,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Scripted;
+    use id3::frame::{ExtendedText, Frame, Unknown};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ebook_organiser_mp3_test_{}.mp3",
+            rand::random::<u64>()
+        ))
+    }
+
+    /// Write an MP3 that is just an ID3v2 tag with the given text frames
+    fn write_mp3(path: &Path, frames: &[(&str, &str)], extended: &[(&str, &str)]) {
+        let mut tag = Tag::new();
+        for (id, text) in frames {
+            if id.starts_with('T') {
+                tag.set_text(*id, *text);
+            } else {
+                // The id3 crate only writes text frames whose ID starts with a T, so others are
+                // written as UTF-8 by hand
+                let data = [&[3], text.as_bytes()].concat();
+                let content = Content::Unknown(Unknown {
+                    data,
+                    version: id3::Version::Id3v24,
+                });
+                tag.add_frame(Frame::with_content(*id, content));
+            }
+        }
+        for (description, value) in extended {
+            tag.add_frame(ExtendedText {
+                description: description.to_string(),
+                value: value.to_string(),
+            });
+        }
+        fs::write(path, b"").unwrap();
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+    }
+
+    /// Append an ID3v1 tag, which the id3 crate can read but not write
+    fn append_id3v1(path: &Path, title: &str, artist: &str) {
+        let field = |text: &str, len: usize| {
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.resize(len, 0);
+            bytes
+        };
+        let mut contents = fs::read(path).unwrap();
+        contents.extend(b"TAG");
+        contents.extend(field(title, 30));
+        contents.extend(field(artist, 30));
+        contents.extend(field("", 30 + 4 + 30));
+        contents.push(255);
+        fs::write(path, contents).unwrap();
+    }
+
+    fn parse(path: &Path) -> FileMetadata {
+        parse_mp3(path, &Scripted::new(&[])).unwrap()
+    }
+
+    #[test]
+    fn test_id3v1_fallback() -> std::io::Result<()> {
+        let path = temp_path();
+        write_mp3(&path, &[("TIT2", "The Colour of Magic")], &[]);
+        append_id3v1(&path, "Colour of Magic", "PRATCHETT, TERRY");
+
+        // The ID3v2 title is kept, and the missing author comes from ID3v1
+        let metadata = parse(&path);
+        assert_eq!(metadata.title, "The Colour of Magic");
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+
+        // An ID3v1 tag on its own is enough
+        fs::write(&path, b"")?;
+        append_id3v1(&path, "Mort", "Terry Pratchett");
+        let metadata = parse(&path);
+        assert_eq!(metadata.title, "Mort");
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+
+        fs::remove_file(&path)
+    }
+
+    #[test]
+    fn test_author_fallback_order() -> std::io::Result<()> {
+        let path = temp_path();
+        let author = |frames: &[(&str, &str)]| {
+            write_mp3(&path, frames, &[]);
+            parse(&path).main_author
+        };
+        let title = ("TIT2", "Title");
+
+        assert_eq!(
+            author(&[
+                title,
+                ("TCOM", "Composer"),
+                ("TPE2", "Album Artist"),
+                ("TPE1", "Artist")
+            ]),
+            "Artist"
+        );
+        assert_eq!(
+            author(&[title, ("TCOM", "Composer"), ("TPE2", "Album Artist")]),
+            "Album Artist"
+        );
+        assert_eq!(author(&[title, ("TCOM", "Composer")]), "Composer");
+
+        fs::remove_file(&path)
+    }
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(decode_text(b"\0Caf\xe9").as_deref(), Some("Café"));
+        assert_eq!(
+            decode_text(b"\x01\xff\xfe1\x00/\x008\x00").as_deref(),
+            Some("1/8")
+        );
+        assert_eq!(decode_text(b"\x03One\0Two").as_deref(), Some("One"));
+        assert_eq!(decode_text(b""), None);
+    }
+
+    #[test]
+    fn test_series() -> std::io::Result<()> {
+        let path = temp_path();
+        let frames = [
+            ("TIT2", "Guards! Guards!"),
+            ("TPE1", "Terry Pratchett"),
+            ("MVNM", "The Watch"),
+            ("MVIN", "1/8"),
+        ];
+
+        write_mp3(&path, &frames, &[]);
+        let metadata = parse(&path);
+        assert_eq!(metadata.series.as_deref(), Some("The Watch"));
+        assert_eq!(metadata.series_index.as_deref(), Some("1"));
+
+        // TXXX:SERIES wins over the movement frames
+        write_mp3(
+            &path,
+            &frames,
+            &[("series", "Discworld"), ("SERIES-PART", "8.0")],
+        );
+        let metadata = parse(&path);
+        assert_eq!(metadata.series.as_deref(), Some("Discworld"));
+        assert_eq!(metadata.series_index.as_deref(), Some("8"));
+
+        fs::remove_file(&path)
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use lopdf::{Document, decode_text_string};
+
+use crate::{names::normalise_authors, parsers::metadata::FileMetadata, prompt::Interactor};
+
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+#[derive(Default)]
+struct PdfFields {
+    title: Option<String>,
+    author: Option<String>,
+    language: Option<String>,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+fn read_info_dictionary(doc: &Document) -> PdfFields {
+    let Ok(info) = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+    else {
+        return PdfFields::default();
+    };
+    let read = |key: &[u8]| {
+        info.get(key)
+            .and_then(|value| doc.dereference(value))
+            .and_then(|(_, value)| decode_text_string(value))
+            .ok()
+            .and_then(non_empty)
+    };
+    PdfFields {
+        title: read(b"Title"),
+        author: read(b"Author"),
+        ..Default::default()
+    }
+}
+
+/// Pull dc:title, the first dc:creator and the first dc:language out of an XMP packet
+fn parse_xmp(xml: &str) -> PdfFields {
+    let Ok(xmp) = roxmltree::Document::parse(xml) else {
+        return PdfFields::default();
+    };
+    // Each field holds its values in rdf:li children (rdf:Alt for title, rdf:Seq for creator
+    // and rdf:Bag for language)
+    let first_item = |name: &str| {
+        xmp.descendants()
+            .filter(|n| n.has_tag_name((DC_NAMESPACE, name)))
+            .flat_map(|n| n.descendants())
+            .filter(|n| n.tag_name().name() == "li")
+            .find_map(|n| n.text().map(str::to_owned).and_then(non_empty))
+    };
+    PdfFields {
+        title: first_item("title"),
+        author: first_item("creator"),
+        language: first_item("language"),
+    }
+}
+
+fn read_xmp(doc: &Document) -> PdfFields {
+    let stream = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(|metadata| doc.dereference(metadata))
+        .and_then(|(_, metadata)| metadata.as_stream());
+    let Ok(stream) = stream else {
+        return PdfFields::default();
+    };
+    // XMP is normally stored uncompressed, but some writers deflate it anyway
+    let content = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    parse_xmp(&String::from_utf8_lossy(&content))
+}
+
+pub fn parse_pdf(file_path: &Path, interactor: &dyn Interactor) -> Result<FileMetadata, String> {
+    // Open the PDF file and read its Info dictionary and XMP metadata
+    match Document::load(file_path) {
+        Ok(doc) => {
+            // XMP is usually better maintained than the legacy Info dictionary, so prefer it
+            let info = read_info_dictionary(&doc);
+            let xmp = read_xmp(&doc);
+
+            let title = xmp.title.or(info.title).unwrap_or_else(|| {
+                interactor
+                    .ask_text(&format!(
+                        "No title found for {}, please enter one:",
+                        file_path.display()
+                    ))
+                    .unwrap_or_default()
+            });
+            let selected_author = xmp
+                .author
+                .or(info.author)
+                .map(|author| normalise_authors(&author))
+                .unwrap_or_else(|| {
+                    interactor
+                        .ask_text(&format!(
+                            "No author found for {}, please enter one:",
+                            file_path.display()
+                        ))
+                        .unwrap_or_default()
+                });
+
+            Ok(FileMetadata {
+                title: title.trim().to_owned(),
+                main_author: selected_author.trim().to_owned(),
+                language: xmp.language,
+                ..Default::default()
+            })
+        }
+        Err(e) => {
+            println!("Failed to parse PDF file: {e:?}");
+            Err(e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Scripted;
+    use lopdf::{Object, Stream, dictionary};
+
+    const XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Good Omens</rdf:li></rdf:Alt></dc:title>
+      <dc:creator><rdf:Seq><rdf:li> </rdf:li><rdf:li>Terry Pratchett</rdf:li><rdf:li>Neil Gaiman</rdf:li></rdf:Seq></dc:creator>
+      <dc:language><rdf:Bag><rdf:li>en-GB</rdf:li></rdf:Bag></dc:language>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>"#;
+
+    /// Write a one page PDF with the given Info dictionary title and author, and XMP packet
+    fn write_pdf(path: &Path, title: &str, author: &str, xmp: Option<&str>) -> lopdf::Result<()> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.add_object(dictionary! {
+            "Type" => "Pages",
+            "Kids" => Vec::<Object>::new(),
+            "Count" => 0,
+        });
+        let mut catalog = dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        };
+        if let Some(xmp) = xmp {
+            let metadata = Stream::new(
+                dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+                xmp.as_bytes().to_vec(),
+            );
+            catalog.set("Metadata", doc.add_object(metadata));
+        }
+        let catalog_id = doc.add_object(catalog);
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal(title),
+            "Author" => Object::string_literal(author),
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.trailer.set("Info", info_id);
+        doc.save(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_xmp() {
+        let fields = parse_xmp(XMP);
+        assert_eq!(fields.title.as_deref(), Some("Good Omens"));
+        // The first creator that isn't blank is the main author
+        assert_eq!(fields.author.as_deref(), Some("Terry Pratchett"));
+        assert_eq!(fields.language.as_deref(), Some("en-GB"));
+
+        let fields = parse_xmp("not xml");
+        assert!(fields.title.is_none() && fields.author.is_none());
+    }
+
+    #[test]
+    fn test_xmp_wins_over_info() -> lopdf::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_pdf_test_{}.pdf",
+            rand::random::<u64>()
+        ));
+        let scripted = Scripted::new(&[]);
+
+        write_pdf(&path, "Info Title", "PRATCHETT, TERRY", Some(XMP))?;
+        let metadata = parse_pdf(&path, &scripted).unwrap();
+        assert_eq!(metadata.title, "Good Omens");
+        assert_eq!(metadata.main_author, "Terry Pratchett");
+        assert_eq!(metadata.language.as_deref(), Some("en-GB"));
+
+        // Without XMP, or with fields missing from it, the Info dictionary is used
+        let title_only = XMP.replace(
+            "<rdf:li>Terry Pratchett</rdf:li><rdf:li>Neil Gaiman</rdf:li>",
+            "",
+        );
+        write_pdf(&path, "Info Title", "GAIMAN, NEIL", Some(&title_only))?;
+        let metadata = parse_pdf(&path, &scripted).unwrap();
+        assert_eq!(metadata.title, "Good Omens");
+        assert_eq!(metadata.main_author, "Neil Gaiman");
+
+        write_pdf(&path, "Info Title", "Neil Gaiman", None)?;
+        let metadata = parse_pdf(&path, &scripted).unwrap();
+        assert_eq!(metadata.title, "Info Title");
+        assert_eq!(metadata.language, None);
+        assert_eq!(scripted.questions_asked(), 0);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
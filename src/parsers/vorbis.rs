@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use crate::{
+    names::normalise_authors,
+    parsers::{audiobook_folder::AudioTags, metadata::FileMetadata, misc::clean_series_index},
+    prompt::Interactor,
+};
+
+/// Vorbis comments, keyed by upper case field name as field names are case insensitive
+type Comments = HashMap<String, Vec<String>>;
+
+/// FLAC metadata block type holding the Vorbis comments
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Parse a Vorbis comment block: vendor string, then a count of "KEY=value" strings
+fn parse_comment_block(block: &[u8]) -> Result<Comments, String> {
+    let truncated = || String::from("truncated Vorbis comment block");
+    let vendor_length = read_u32_le(block, 0).ok_or_else(truncated)? as usize;
+    let mut offset = 4 + vendor_length;
+    let count = read_u32_le(block, offset).ok_or_else(truncated)?;
+    offset += 4;
+
+    let mut comments = Comments::new();
+    for _ in 0..count {
+        let length = read_u32_le(block, offset).ok_or_else(truncated)? as usize;
+        offset += 4;
+        let comment = block.get(offset..offset + length).ok_or_else(truncated)?;
+        offset += length;
+        let comment = String::from_utf8_lossy(comment);
+        if let Some((key, value)) = comment.split_once('=') {
+            comments
+                .entry(key.to_uppercase())
+                .or_default()
+                .push(value.to_owned());
+        }
+    }
+    Ok(comments)
+}
+
+/// Find the VORBIS_COMMENT metadata block of a FLAC stream, after the "fLaC" marker
+fn read_flac_comments(reader: &mut impl Read) -> Result<Comments, String> {
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        let mut block = vec![0u8; length];
+        reader.read_exact(&mut block).map_err(|e| e.to_string())?;
+        if block_type == FLAC_VORBIS_COMMENT {
+            return parse_comment_block(&block);
+        }
+        if is_last {
+            return Ok(Comments::new());
+        }
+    }
+}
+
+/// Read the packets of the first logical stream of an Ogg file until `count` have been collected
+fn read_ogg_packets(reader: &mut impl Read, count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    let mut stream_serial = None;
+    // The first page's "OggS" capture pattern has already been consumed
+    let mut capture = *b"OggS";
+    loop {
+        if &capture != b"OggS" {
+            return Err(String::from("invalid Ogg page"));
+        }
+        // version, header type, granule position, serial, sequence number, checksum, segment count
+        let mut header = [0u8; 23];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let serial = u32::from_le_bytes([header[10], header[11], header[12], header[13]]);
+        let mut lacing = vec![0u8; header[22] as usize];
+        reader.read_exact(&mut lacing).map_err(|e| e.to_string())?;
+        let mut body = vec![0u8; lacing.iter().map(|&l| l as usize).sum()];
+        reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+
+        if *stream_serial.get_or_insert(serial) == serial {
+            // A lacing value below 255 ends a packet, 255 means it continues in the next segment
+            let mut offset = 0;
+            for &length in &lacing {
+                packet.extend_from_slice(&body[offset..offset + length as usize]);
+                offset += length as usize;
+                if length < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                    if packets.len() == count {
+                        return Ok(packets);
+                    }
+                }
+            }
+        }
+
+        reader.read_exact(&mut capture).map_err(|e| e.to_string())?;
+    }
+}
+
+/// The comment header is the second packet of both Opus and Vorbis streams
+fn read_ogg_comments(reader: &mut impl Read) -> Result<Comments, String> {
+    let packets = read_ogg_packets(reader, 2)?;
+    let comment_header = &packets[1];
+    if let Some(block) = comment_header.strip_prefix(b"OpusTags") {
+        parse_comment_block(block)
+    } else if let Some(block) = comment_header.strip_prefix(b"\x03vorbis") {
+        parse_comment_block(block)
+    } else {
+        Err(String::from("unsupported Ogg codec"))
+    }
+}
+
+fn read_comments(reader: &mut impl Read) -> Result<Comments, String> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    match &magic {
+        b"fLaC" => read_flac_comments(reader),
+        b"OggS" => read_ogg_comments(reader),
+        _ => Err(String::from("not a FLAC or Ogg file")),
+    }
+}
+
+fn first_comment(comments: &Comments, key: &str) -> Option<String> {
+    comments
+        .get(key)?
+        .iter()
+        .map(|value| value.trim())
+        .find(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+fn read_file_comments(file_path: &Path) -> Result<Comments, String> {
+    File::open(file_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| read_comments(&mut BufReader::new(file)))
+}
+
+/// Read the album and author tags without prompting
+pub fn read_vorbis_tags(file_path: &Path) -> Option<AudioTags> {
+    let comments = read_file_comments(file_path).ok()?;
+    let author =
+        first_comment(&comments, "ARTIST").or_else(|| first_comment(&comments, "COMPOSER"));
+    Some(AudioTags::new(
+        first_comment(&comments, "ALBUM").as_deref(),
+        author.as_deref(),
+    ))
+}
+
+pub fn parse_vorbis(file_path: &Path, interactor: &dyn Interactor) -> Result<FileMetadata, String> {
+    // Open the opus/ogg/flac file and read its Vorbis comments
+    match read_file_comments(file_path) {
+        Ok(comments) => {
+            // Fall back to the album as the title, the same as MP4 audiobooks
+            let title = first_comment(&comments, "TITLE")
+                .or_else(|| first_comment(&comments, "ALBUM"))
+                .unwrap_or_else(|| {
+                    interactor
+                        .ask_text(&format!(
+                            "No title found for {}, please enter one:",
+                            file_path.display()
+                        ))
+                        .unwrap_or_default()
+                });
+
+            let author = first_comment(&comments, "ARTIST");
+            let composer = first_comment(&comments, "COMPOSER");
+            let selected_author = if let Some(author) = author {
+                normalise_authors(&author)
+            } else if let Some(author) = composer {
+                normalise_authors(&author)
+            } else {
+                let artist_options: Vec<String> = ["ALBUMARTIST", "PERFORMER"]
+                    .iter()
+                    .filter_map(|key| comments.get(*key))
+                    .flatten()
+                    .map(|artist| normalise_authors(artist))
+                    .collect();
+                interactor
+                    .select(
+                        &format!(
+                            "No author found for {}, please enter one:",
+                            file_path.display()
+                        ),
+                        &artist_options,
+                    )
+                    .unwrap_or_default()
+            };
+
+            let series = first_comment(&comments, "SERIES");
+            let series_index = series
+                .as_ref()
+                .and(first_comment(&comments, "SERIES-PART"))
+                .and_then(|i| clean_series_index(&i));
+
+            Ok(FileMetadata {
+                title: title.trim().to_owned(),
+                main_author: selected_author.trim().to_owned(),
+                series,
+                series_index,
+                ..Default::default()
+            })
+        }
+        Err(e) => {
+            println!("Failed to parse audiobook file: {e}");
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment_block(comments: &[&str]) -> Vec<u8> {
+        let vendor = b"test vendor";
+        let mut block = Vec::new();
+        block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        block.extend_from_slice(vendor);
+        block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            block.extend_from_slice(comment.as_bytes());
+        }
+        block
+    }
+
+    /// Build a page holding one packet, or the start of one that continues on the next page
+    fn ogg_page(serial: u32, packet: &[u8], ends_packet: bool) -> Vec<u8> {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        if ends_packet {
+            lacing.push((packet.len() % 255) as u8);
+        }
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0, 0]);
+        page.extend_from_slice(&[0; 8]);
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(lacing.len() as u8);
+        page.extend_from_slice(&lacing);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    #[test]
+    fn test_read_flac_comments() {
+        let block = comment_block(&["title=The Hobbit", "ARTIST=J. R. R. Tolkien"]);
+        let mut flac = b"fLaC".to_vec();
+        // A STREAMINFO block, then the (last) comment block
+        flac.extend_from_slice(&[0, 0, 0, 34]);
+        flac.extend_from_slice(&[0; 34]);
+        flac.push(0x80 | FLAC_VORBIS_COMMENT);
+        flac.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        flac.extend_from_slice(&block);
+
+        let comments = read_comments(&mut flac.as_slice()).unwrap();
+        assert_eq!(first_comment(&comments, "TITLE").unwrap(), "The Hobbit");
+        assert_eq!(
+            first_comment(&comments, "ARTIST").unwrap(),
+            "J. R. R. Tolkien"
+        );
+    }
+
+    #[test]
+    fn test_read_opus_comments_across_pages() {
+        // A long comment forces the comment packet over several lacing segments and two pages
+        let long_title = format!("TITLE={}", "x".repeat(600));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend(comment_block(&[&long_title, "ALBUM=Album"]));
+        let (first, second) = tags.split_at(255 * 2);
+
+        let mut ogg = ogg_page(7, b"OpusHead", true);
+        // A page from another logical stream should be ignored
+        ogg.extend(ogg_page(9, b"\x03vorbis", true));
+        ogg.extend(ogg_page(7, first, false));
+        ogg.extend(ogg_page(7, second, true));
+
+        let comments = read_comments(&mut ogg.as_slice()).unwrap();
+        assert_eq!(first_comment(&comments, "TITLE").unwrap().len(), 600);
+        assert_eq!(first_comment(&comments, "ALBUM").unwrap(), "Album");
+    }
+}
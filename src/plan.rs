@@ -0,0 +1,348 @@
+use crate::fileops::{missing_folders, transfer};
+use crate::journal::Journal;
+use crate::parsers::FileMetadata;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file operation a plan entry will perform
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Move,
+    Copy,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Move => write!(f, "move"),
+            Operation::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+/// What would happen to a file if the plan was carried out
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PlanStatus {
+    /// The file would be moved or copied to its destination
+    Pending,
+    /// The file is already at its destination
+    InPlace,
+    /// The destination already holds the same file, so the copy would be skipped
+    Identical,
+    /// The destination already exists, or is claimed by an earlier entry in the plan
+    Collision,
+    /// The destination already exists and would be replaced
+    Replace,
+    /// The same file is already elsewhere in the library, so it is left out of the plan
+    Duplicate { existing: PathBuf },
+    /// The same file is already elsewhere in the library, and would be removed to make way
+    /// for this one
+    ReplaceDuplicate { existing: PathBuf },
+    /// No destination could be worked out for the file
+    Skipped { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub source: PathBuf,
+    pub destination: Option<PathBuf>,
+    pub operation: Operation,
+    #[serde(flatten)]
+    pub status: PlanStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<FileMetadata>,
+}
+
+impl PlanEntry {
+    pub fn skipped(source: &Path, operation: Operation, reason: &str) -> Self {
+        PlanEntry {
+            source: source.to_path_buf(),
+            destination: None,
+            operation,
+            status: PlanStatus::Skipped {
+                reason: reason.to_owned(),
+            },
+            metadata: None,
+        }
+    }
+}
+
+impl fmt::Display for PlanEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = self.source.display();
+        let destination = self
+            .destination
+            .as_deref()
+            .unwrap_or_else(|| Path::new(""))
+            .display();
+        match &self.status {
+            PlanStatus::Pending => write!(f, "[{}] {source} -> {destination}", self.operation),
+            PlanStatus::InPlace => write!(f, "[in place] {source}"),
+            PlanStatus::Identical => {
+                write!(f, "[identical] {source} -> {destination}")
+            }
+            PlanStatus::Collision => {
+                write!(f, "[collision] {source} -> {destination}")
+            }
+            PlanStatus::Replace => {
+                write!(
+                    f,
+                    "[{} and replace] {source} -> {destination}",
+                    self.operation
+                )
+            }
+            PlanStatus::Duplicate { existing } => {
+                write!(f, "[duplicate] {source} (same as {})", existing.display())
+            }
+            PlanStatus::ReplaceDuplicate { existing } => write!(
+                f,
+                "[{} and replace duplicate] {source} -> {destination} (removing {})",
+                self.operation,
+                existing.display()
+            ),
+            PlanStatus::Skipped { reason } => write!(f, "[skipped] {source}: {reason}"),
+        }
+    }
+}
+
+/// The complete list of actions a sort run would take
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Plan {
+    pub entries: Vec<PlanEntry>,
+}
+
+impl Plan {
+    /// Add an entry, marking it as a collision if an earlier entry already targets the same destination
+    pub fn push(&mut self, mut entry: PlanEntry) {
+        if matches!(
+            entry.status,
+            PlanStatus::Pending | PlanStatus::Replace | PlanStatus::ReplaceDuplicate { .. }
+        ) && let Some(destination) = &entry.destination
+            && self.claims(destination)
+        {
+            entry.status = PlanStatus::Collision;
+        }
+        self.entries.push(entry);
+    }
+
+    /// Whether an entry already in the plan will end up at this destination
+    pub fn claims(&self, destination: &Path) -> bool {
+        self.entries.iter().any(|e| {
+            e.destination.as_deref() == Some(destination)
+                && matches!(
+                    e.status,
+                    PlanStatus::Pending
+                        | PlanStatus::Replace
+                        | PlanStatus::ReplaceDuplicate { .. }
+                        | PlanStatus::InPlace
+                )
+        })
+    }
+
+    pub fn count(&self, predicate: impl Fn(&PlanStatus) -> bool) -> usize {
+        self.entries.iter().filter(|e| predicate(&e.status)).count()
+    }
+
+    pub fn print(&self) {
+        for entry in &self.entries {
+            println!("{entry}");
+        }
+        println!(
+            "{} to process, {} to replace, {} already in place, {} identical, {} collisions, {} duplicates, {} skipped",
+            self.count(|s| *s == PlanStatus::Pending),
+            self.count(|s| matches!(s, PlanStatus::Replace | PlanStatus::ReplaceDuplicate { .. })),
+            self.count(|s| *s == PlanStatus::InPlace),
+            self.count(|s| *s == PlanStatus::Identical),
+            self.count(|s| *s == PlanStatus::Collision),
+            self.count(|s| matches!(s, PlanStatus::Duplicate { .. })),
+            self.count(|s| matches!(s, PlanStatus::Skipped { .. })),
+        );
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let plan: Plan = serde_json::from_str(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let serialized = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, serialized)
+    }
+
+    /// Carry out every pending entry, and replace the destination of every `replace` entry
+    /// and the duplicate of every `replace_duplicate` entry, without asking for confirmation.
+    ///
+    /// Entries whose source has gone missing or whose destination has appeared since the
+    /// plan was made are left alone. Returns the number of files moved or copied.
+    pub fn apply(&self, journal: &Journal) -> usize {
+        let mut done = 0;
+        for entry in &self.entries {
+            let Some(destination) = &entry.destination else {
+                eprintln!("No destination for {}, skipping", entry.source.display());
+                continue;
+            };
+            let replaced = match &entry.status {
+                PlanStatus::Pending => None,
+                PlanStatus::Replace => Some(destination),
+                PlanStatus::ReplaceDuplicate { existing } => Some(existing),
+                _ => continue,
+            };
+            if !entry.source.exists() {
+                eprintln!(
+                    "Source {} no longer exists, skipping",
+                    entry.source.display()
+                );
+                continue;
+            }
+            if replaced != Some(destination) && destination.exists() {
+                eprintln!(
+                    "Destination {} already exists, skipping",
+                    destination.display()
+                );
+                continue;
+            }
+            // Set aside rather than deleted, so that undo can put it back
+            if let Some(replaced) = replaced
+                && replaced.exists()
+            {
+                match journal.set_aside(replaced) {
+                    Ok(_) => println!("Removed {}.", replaced.display()),
+                    Err(e) => {
+                        eprintln!("Failed to remove {}: {e}", replaced.display());
+                        continue;
+                    }
+                }
+            }
+            let created = missing_folders(destination);
+            if transfer(entry.operation, &entry.source, destination) {
+                journal.record(entry.operation, &entry.source, destination, &created);
+                done += 1;
+            }
+        }
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(source: &str, destination: &str) -> PlanEntry {
+        PlanEntry {
+            source: PathBuf::from(source),
+            destination: Some(PathBuf::from(destination)),
+            operation: Operation::Move,
+            status: PlanStatus::Pending,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_push_marks_duplicate_destinations() {
+        let mut plan = Plan::default();
+        plan.push(pending("/in/a.epub", "/lib/Author/Title.epub"));
+        plan.push(pending("/in/b.epub", "/lib/Author/Title.epub"));
+        plan.push(pending("/in/c.epub", "/lib/Author/Other.epub"));
+
+        assert_eq!(plan.entries[0].status, PlanStatus::Pending);
+        assert_eq!(plan.entries[1].status, PlanStatus::Collision);
+        assert_eq!(plan.entries[2].status, PlanStatus::Pending);
+    }
+
+    #[test]
+    fn test_plan_serialises_status_inline() {
+        let mut plan = Plan::default();
+        plan.push(PlanEntry::skipped(
+            Path::new("/in/a.epub"),
+            Operation::Copy,
+            "no metadata",
+        ));
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["entries"][0]["status"], "skipped");
+        assert_eq!(json["entries"][0]["reason"], "no metadata");
+        assert_eq!(json["entries"][0]["operation"], "copy");
+    }
+
+    #[test]
+    fn test_save_and_load_plan() -> io::Result<()> {
+        let mut plan = Plan::default();
+        let mut entry = pending("/in/a.epub", "/lib/Author/Title.epub");
+        entry.metadata = Some(FileMetadata {
+            title: String::from("Title"),
+            main_author: String::from("Author"),
+            ..Default::default()
+        });
+        plan.push(entry);
+        plan.push(PlanEntry::skipped(
+            Path::new("/in/b.epub"),
+            Operation::Move,
+            "no metadata",
+        ));
+
+        let mut temp_path = std::env::temp_dir();
+        temp_path.push(format!(
+            "ebook_organiser_plan_test_{}.json",
+            rand::random::<u64>()
+        ));
+        plan.save(&temp_path)?;
+        let loaded_plan = Plan::load(&temp_path)?;
+        assert_eq!(loaded_plan, plan);
+
+        fs::remove_file(&temp_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_replace_and_undo() -> io::Result<()> {
+        let root = std::env::temp_dir().join(format!(
+            "ebook_organiser_plan_test_{}",
+            rand::random::<u64>()
+        ));
+        let journal_path = root.join("journal.jsonl");
+        let replaced = root.join("lib/Author/Title.epub");
+        let duplicate = root.join("lib/Old/Title.epub");
+        fs::create_dir_all(root.join("in"))?;
+        fs::create_dir_all(root.join("lib/Author"))?;
+        fs::create_dir_all(root.join("lib/Old"))?;
+        fs::write(&replaced, "replaced")?;
+        fs::write(&duplicate, "duplicate")?;
+        fs::write(root.join("in/a.epub"), "a")?;
+        fs::write(root.join("in/b.epub"), "b")?;
+
+        let mut plan = Plan::default();
+        let mut entry = pending("", "");
+        entry.source = root.join("in/a.epub");
+        entry.destination = Some(replaced.clone());
+        entry.status = PlanStatus::Replace;
+        plan.push(entry);
+        let mut entry = pending("", "");
+        entry.source = root.join("in/b.epub");
+        entry.destination = Some(root.join("lib/Author/Other.epub"));
+        entry.status = PlanStatus::ReplaceDuplicate {
+            existing: duplicate.clone(),
+        };
+        plan.push(entry);
+
+        let journal = Journal::new(&journal_path);
+        assert_eq!(plan.apply(&journal), 2);
+        assert_eq!(fs::read_to_string(&replaced)?, "a");
+        assert!(!duplicate.exists());
+
+        // Both replaced files were set aside, so undo brings them back
+        assert_eq!(crate::journal::undo(&journal_path, None)?, 4);
+        assert_eq!(fs::read_to_string(&replaced)?, "replaced");
+        assert_eq!(fs::read_to_string(&duplicate)?, "duplicate");
+        assert_eq!(fs::read_to_string(root.join("in/a.epub"))?, "a");
+
+        fs::remove_dir_all(&root)
+    }
+}
@@ -1,63 +1,275 @@
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+#[cfg(test)]
+use std::collections::VecDeque;
 use std::io::Write;
 
-pub fn prompt(prompt: &str) -> String {
-    let mut buffer = String::new();
-    let stdin = std::io::stdin();
-    while buffer.is_empty() {
-        print!("{prompt}> ");
-        std::io::stdout().flush().expect("Failed to flush stdout");
-        stdin.read_line(&mut buffer).expect("Failed to read line");
-        buffer = buffer.trim().to_string();
-    }
-    buffer
-}
-
-pub fn prompt_bool(prompt_text: &str) -> bool {
-    loop {
-        let response = prompt(prompt_text);
-        match response.to_lowercase().as_str() {
-            "y" | "yes" => return true,
-            "n" | "no" => return false,
-            _ => println!("Please answer 'y' or 'n'."),
+/// A question that was put off because prompts are turned off
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Question {
+    pub prompt: String,
+    /// The options that would have been offered, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+impl Question {
+    pub fn new(prompt: &str, options: &[String]) -> Self {
+        Question {
+            prompt: prompt.trim().to_owned(),
+            options: options.to_vec(),
         }
     }
 }
 
-pub fn prompt_select_other(prompt_text: &str, options: &[String]) -> String {
-    let mut full_prompt = prompt_text.to_owned()
-        + "please select one or more of the following (comma seperated) or type 'other':\r\n";
-    for (i, option) in options.iter().enumerate() {
-        full_prompt += &format!("[{i}]: {option}\r\n");
+/// The key of a choice such as "keep [l]arger", and the choice without the brackets
+fn choice_key(choice: &str) -> (String, String) {
+    let key = choice
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map_or(choice, |(key, _)| key);
+    (key.to_lowercase(), choice.replace(['[', ']'], ""))
+}
+
+/// Everything that asks the user something goes through this, so parsers and the sorter can
+/// be driven by a terminal, a script or nobody at all.
+///
+/// Each method returns `None` when there is no answer.
+pub trait Interactor {
+    /// Ask for a line of text
+    fn ask_text(&self, prompt: &str) -> Option<String>;
+
+    /// How many questions have been asked so far, answered or not
+    fn questions_asked(&self) -> usize;
+
+    /// Whether anybody is there to answer. Without them, moves go ahead without confirmation.
+    fn is_interactive(&self) -> bool {
+        true
+    }
+
+    /// Take the questions that went unanswered since the last call
+    fn take_declined(&self) -> Vec<Question> {
+        Vec::new()
+    }
+
+    fn ask_bool(&self, prompt: &str) -> Option<bool> {
+        loop {
+            match self.ask_text(prompt)?.to_lowercase().as_str() {
+                "y" | "yes" => return Some(true),
+                "n" | "no" => return Some(false),
+                _ => println!("Please answer 'y' or 'n'."),
+            }
+        }
+    }
+
+    /// Pick one or more of `options` by number, or type another answer. Several options are
+    /// joined with " & ".
+    fn select(&self, prompt: &str, options: &[String]) -> Option<String> {
+        let mut full_prompt = prompt.to_owned()
+            + "please select one or more of the following (comma seperated) or type 'other':\r\n";
+        for (i, option) in options.iter().enumerate() {
+            full_prompt += &format!("[{i}]: {option}\r\n");
+        }
+        loop {
+            let response = self.ask_text(&full_prompt)?;
+            // if response is a number check if we can parse it as an index
+            if response == "other" {
+                return self.ask_text("Please specify your option");
+            } else if let Ok(index) = response.parse::<usize>() {
+                if index < options.len() {
+                    return Some(options[index].to_string());
+                } else {
+                    println!("Index out of range. Please select a valid index.");
+                }
+            } else if response.contains(',') {
+                // Multiple selection,
+                // Split by comma, pick each option from the options list by this index, then join them with '&'
+                let indices: Vec<usize> = response
+                    .split(',')
+                    .map(str::trim)
+                    .filter_map(|s| s.parse::<usize>().ok())
+                    .collect();
+                if indices.iter().all(|&i| i < options.len()) {
+                    return Some(
+                        indices
+                            .iter()
+                            .map(|&i| options[i].to_string())
+                            .collect::<Vec<String>>()
+                            .join(" & "),
+                    );
+                } else {
+                    println!("One or more indices are out of range. Please select valid indices.");
+                }
+            }
+            println!("Input cannot be empty. Please select an option.");
+        }
     }
-    loop {
-        let response = prompt(&full_prompt);
-        // if response is a number check if we can parse it as an index
-        if response == "other" {
-            return prompt("Please specify your option");
-        } else if let Ok(index) = response.parse::<usize>() {
-            if index < options.len() {
-                return options[index].to_string();
-            } else {
-                println!("Index out of range. Please select a valid index.");
+
+    /// Pick one of a fixed set of `choices`, each with its key in brackets as in "keep [l]arger",
+    /// returning its index
+    fn choose(&self, prompt: &str, choices: &[&str]) -> Option<usize> {
+        let keys: Vec<(String, String)> = choices.iter().map(|choice| choice_key(choice)).collect();
+        let full_prompt = format!("{prompt} {}", choices.join(", "));
+        loop {
+            let response = self.ask_text(&full_prompt)?.trim().to_lowercase();
+            if let Some(index) = keys
+                .iter()
+                .position(|(key, name)| response == *key || response == name.to_lowercase())
+            {
+                return Some(index);
             }
-        } else if response.contains(',') {
-            // Multiple selection,
-            // Split by comma, pick each option from the options list by this index, then join them with '&'
-            let indices: Vec<usize> = response
-                .split(',')
-                .map(str::trim)
-                .filter_map(|s| s.parse::<usize>().ok())
-                .collect();
-            if indices.iter().all(|&i| i < options.len()) {
-                return indices
-                    .iter()
-                    .map(|&i| options[i].to_string())
-                    .collect::<Vec<String>>()
-                    .join(" & ");
-            } else {
-                println!("One or more indices are out of range. Please select valid indices.");
+            let keys: Vec<&str> = keys.iter().map(|(key, _)| key.as_str()).collect();
+            println!("Please answer one of: {}.", keys.join(", "));
+        }
+    }
+}
+
+/// Asks on the terminal
+#[derive(Default)]
+pub struct Terminal {
+    asked: Cell<usize>,
+}
+
+impl Interactor for Terminal {
+    /// Keeps asking until the answer isn't empty, giving up at the end of input
+    fn ask_text(&self, prompt: &str) -> Option<String> {
+        self.asked.set(self.asked.get() + 1);
+        let mut buffer = String::new();
+        let stdin = std::io::stdin();
+        while buffer.is_empty() {
+            print!("{prompt}> ");
+            std::io::stdout().flush().expect("Failed to flush stdout");
+            if stdin.read_line(&mut buffer).expect("Failed to read line") == 0 {
+                println!();
+                return None;
             }
+            buffer = buffer.trim().to_string();
         }
-        println!("Input cannot be empty. Please select an option.");
+        Some(buffer)
+    }
+
+    fn questions_asked(&self) -> usize {
+        self.asked.get()
+    }
+}
+
+/// Answers nothing, recording each question so it can be asked later
+#[derive(Default)]
+pub struct NonInteractive {
+    declined: RefCell<Vec<Question>>,
+    asked: Cell<usize>,
+}
+
+impl NonInteractive {
+    fn decline<T>(&self, prompt: &str, options: &[String]) -> Option<T> {
+        self.asked.set(self.asked.get() + 1);
+        self.declined
+            .borrow_mut()
+            .push(Question::new(prompt, options));
+        None
+    }
+}
+
+impl Interactor for NonInteractive {
+    fn ask_text(&self, prompt: &str) -> Option<String> {
+        self.decline(prompt, &[])
+    }
+
+    fn questions_asked(&self) -> usize {
+        self.asked.get()
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+
+    fn take_declined(&self) -> Vec<Question> {
+        self.declined.take()
+    }
+
+    fn ask_bool(&self, prompt: &str) -> Option<bool> {
+        self.decline(prompt, &[])
+    }
+
+    fn select(&self, prompt: &str, options: &[String]) -> Option<String> {
+        self.decline(prompt, options)
+    }
+
+    fn choose(&self, prompt: &str, choices: &[&str]) -> Option<usize> {
+        let options: Vec<String> = choices.iter().map(|choice| choice_key(choice).1).collect();
+        self.decline(prompt, &options)
+    }
+}
+
+/// Gives a fixed list of answers in order, for tests
+#[cfg(test)]
+pub struct Scripted {
+    answers: RefCell<VecDeque<String>>,
+    /// Every prompt asked, in order
+    pub prompts: RefCell<Vec<String>>,
+}
+
+#[cfg(test)]
+impl Scripted {
+    pub fn new(answers: &[&str]) -> Self {
+        Scripted {
+            answers: RefCell::new(answers.iter().map(|a| a.to_string()).collect()),
+            prompts: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Interactor for Scripted {
+    fn ask_text(&self, prompt: &str) -> Option<String> {
+        self.prompts.borrow_mut().push(prompt.to_owned());
+        self.answers.borrow_mut().pop_front()
+    }
+
+    fn questions_asked(&self) -> usize {
+        self.prompts.borrow().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_answers() {
+        let options = vec![String::from("Terry Pratchett"), String::from("Neil Gaiman")];
+        let scripted = Scripted::new(&["5", "0, 1", "maybe", "keep larger", "L"]);
+        // An out of range answer is asked again
+        assert_eq!(
+            scripted.select("Author?", &options).as_deref(),
+            Some("Terry Pratchett & Neil Gaiman")
+        );
+        let choices = ["[s]kip", "keep [l]arger"];
+        assert_eq!(scripted.choose("Collision?", &choices), Some(1));
+        assert_eq!(scripted.choose("Collision?", &choices), Some(1));
+        // Out of answers
+        assert_eq!(scripted.ask_bool("OK?"), None);
+        assert_eq!(scripted.questions_asked(), 6);
+    }
+
+    #[test]
+    fn test_non_interactive_declines() {
+        let interactor = NonInteractive::default();
+        assert_eq!(
+            interactor.choose("Collision?", &["[s]kip", "keep [l]arger"]),
+            None
+        );
+        assert_eq!(interactor.ask_text("Title?"), None);
+        assert_eq!(
+            interactor.take_declined(),
+            vec![
+                Question::new(
+                    "Collision?",
+                    &[String::from("skip"), String::from("keep larger")]
+                ),
+                Question::new("Title?", &[]),
+            ]
+        );
+        assert!(interactor.take_declined().is_empty());
     }
 }
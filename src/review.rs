@@ -0,0 +1,116 @@
+use crate::prompt::Question;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file a non-interactive run left alone, and the questions it would have asked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    pub source: PathBuf,
+    /// The folder the run was sorting, which routing rules match folders below
+    pub source_root: PathBuf,
+    /// The library the file would have gone to before routing rules, and its template
+    pub library: PathBuf,
+    pub template: String,
+    pub questions: Vec<Question>,
+}
+
+/// Files put off by non-interactive runs, kept as a JSON list until they are reviewed
+pub struct ReviewQueue {
+    path: PathBuf,
+    entries: RefCell<Vec<ReviewEntry>>,
+}
+
+impl ReviewQueue {
+    /// Load the queue at `path`, which is empty if the file doesn't exist yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(ReviewQueue {
+            path: path.to_path_buf(),
+            entries: RefCell::new(entries),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a file to the queue, replacing any earlier entry for it
+    pub fn add(&self, entry: ReviewEntry) {
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|e| e.source != entry.source);
+        entries.push(entry);
+    }
+
+    pub fn remove(&self, source: &Path) {
+        self.entries.borrow_mut().retain(|e| e.source != source);
+    }
+
+    pub fn entries(&self) -> Vec<ReviewEntry> {
+        self.entries.borrow().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let serialized = serde_json::to_string_pretty(&*self.entries.borrow())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&self.path, serialized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_review_queue_round_trip() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_review_test_{}.json",
+            rand::random::<u64>()
+        ));
+        let queue = ReviewQueue::load(&path)?;
+        assert!(queue.is_empty());
+
+        let entry = ReviewEntry {
+            source: PathBuf::from("/in/a.epub"),
+            source_root: PathBuf::from("/in"),
+            library: PathBuf::from("/lib"),
+            template: String::from("{author}/{title}.{ext}"),
+            questions: vec![Question::new(
+                "No author found for /in/a.epub",
+                &[
+                    String::from("Pratchett, Terry"),
+                    String::from("Terry Pratchett"),
+                ],
+            )],
+        };
+        queue.add(entry.clone());
+        // Queuing the same file again replaces its entry
+        queue.add(entry.clone());
+        queue.save()?;
+
+        let loaded = ReviewQueue::load(&path)?;
+        assert_eq!(loaded.entries(), vec![entry]);
+        loaded.remove(Path::new("/in/a.epub"));
+        assert!(loaded.is_empty());
+
+        fs::remove_file(&path)
+    }
+}
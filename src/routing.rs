@@ -0,0 +1,132 @@
+use crate::parsers::{FileMetadata, file_extension};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path};
+
+/// Sends matching files to a library of their own, such as French books or manga.
+///
+/// Every condition that is set has to match. Text is compared ignoring case, and a language
+/// also matches its regional variants, so "fr" matches "fr-CA".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rule {
+    /// File extensions, without the dot
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Matched against every subject or genre of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Name of a folder the file is in, below the source folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Root folder of the library that matching files are sorted into
+    pub library: String,
+    /// Template for the library, the template the file would otherwise use if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+fn same_text(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+impl Rule {
+    /// Whether the rule names this extension, so files the libraries don't sort still get picked up
+    pub fn names_extension(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|e| same_text(e, extension))
+    }
+
+    pub fn matches(&self, file_path: &Path, source_root: &Path, metadata: &FileMetadata) -> bool {
+        let extension_matches = self.extensions.is_empty()
+            || file_extension(file_path).is_some_and(|ext| self.names_extension(&ext));
+        let language_matches = self.language.as_ref().is_none_or(|wanted| {
+            metadata.language.as_deref().is_some_and(|language| {
+                let language = language.trim().to_lowercase();
+                let wanted = wanted.trim().to_lowercase();
+                language == wanted || language.starts_with(&format!("{wanted}-"))
+            })
+        });
+        let genre_matches = self.genre.as_ref().is_none_or(|wanted| {
+            metadata
+                .subjects
+                .iter()
+                .any(|subject| same_text(subject, wanted))
+        });
+        let author_matches = self
+            .author
+            .as_ref()
+            .is_none_or(|wanted| same_text(&metadata.main_author, wanted));
+        let folder_matches = self.folder.as_ref().is_none_or(|wanted| {
+            let relative = file_path.strip_prefix(source_root).unwrap_or(file_path);
+            // Only the folders the file is in, not the file (or audiobook folder) itself
+            relative
+                .parent()
+                .into_iter()
+                .flat_map(Path::components)
+                .any(|c| matches!(c, Component::Normal(name) if same_text(&name.to_string_lossy(), wanted)))
+        });
+        extension_matches && language_matches && genre_matches && author_matches && folder_matches
+    }
+}
+
+/// The first rule that matches the file, if any
+pub fn find_rule<'a>(
+    rules: &'a [Rule],
+    file_path: &Path,
+    source_root: &Path,
+    metadata: &FileMetadata,
+) -> Option<&'a Rule> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(file_path, source_root, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_matches() {
+        let metadata = FileMetadata {
+            title: String::from("Akira"),
+            main_author: String::from("Katsuhiro Otomo"),
+            language: Some(String::from("fr-FR")),
+            subjects: vec![String::from("Science Fiction"), String::from("Manga")],
+            ..Default::default()
+        };
+        let source = Path::new("/in");
+        let file = Path::new("/in/Manga/Akira/akira.cbz");
+        let rule = |rule: Rule| rule.matches(file, source, &metadata);
+
+        assert!(rule(Rule {
+            language: Some(String::from("FR")),
+            ..Default::default()
+        }));
+        assert!(rule(Rule {
+            genre: Some(String::from("manga")),
+            extensions: vec![String::from("cbz"), String::from("cbr")],
+            ..Default::default()
+        }));
+        assert!(rule(Rule {
+            folder: Some(String::from("manga")),
+            ..Default::default()
+        }));
+        assert!(!rule(Rule {
+            folder: Some(String::from("akira.cbz")),
+            ..Default::default()
+        }));
+        assert!(!rule(Rule {
+            genre: Some(String::from("manga")),
+            author: Some(String::from("Osamu Tezuka")),
+            ..Default::default()
+        }));
+        // The folders above the source folder don't count
+        assert!(!rule(Rule {
+            folder: Some(String::from("in")),
+            ..Default::default()
+        }));
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// How strictly metadata is cleaned up before it becomes part of a path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStyle {
+    /// Only what Linux and macOS forbid: "/" and control characters
+    #[default]
+    Posix,
+    /// Also the characters and names FAT, exFAT and NTFS forbid, for e-reader storage
+    Windows,
+    /// Windows safe, with everything transliterated to ASCII ("Толстой" becomes "Tolstoi")
+    Ascii,
+}
+
+/// Names Windows reserves for devices, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Cleans up template values and the paths built from them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitiser {
+    pub style: PathStyle,
+    /// Longest allowed file or folder name, in bytes
+    pub max_component_length: usize,
+}
+
+impl Default for Sanitiser {
+    fn default() -> Self {
+        Sanitiser {
+            style: PathStyle::default(),
+            max_component_length: 255,
+        }
+    }
+}
+
+impl Sanitiser {
+    pub fn new(style: PathStyle, max_component_length: usize) -> Self {
+        Sanitiser {
+            style,
+            max_component_length,
+        }
+    }
+
+    /// Clean a single template value, so that "AC/DC" can't create a folder of its own
+    pub fn value(&self, value: &str) -> String {
+        let value = match self.style {
+            PathStyle::Ascii => deunicode::deunicode(value),
+            PathStyle::Posix | PathStyle::Windows => value.to_owned(),
+        };
+        let windows_safe = self.style != PathStyle::Posix;
+        let mut cleaned = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '/' => cleaned.push('-'),
+                c if c.is_control() => {}
+                '\\' | '|' if windows_safe => cleaned.push('-'),
+                // "Who Goes There?: A Novella" reads better as "Who Goes There - A Novella"
+                ':' if windows_safe => cleaned.push_str(" - "),
+                '"' if windows_safe => cleaned.push('\''),
+                '?' | '*' | '<' | '>' if windows_safe => {}
+                c => cleaned.push(c),
+            }
+        }
+        cleaned.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    /// Clean each component of a rendered template, keeping `extension` on the last one when
+    /// it has to be shortened. Empty, "." and ".." components are dropped.
+    pub fn path(&self, path: &str, extension: &str) -> PathBuf {
+        let components: Vec<&str> = Path::new(path)
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+        let last = components.len().saturating_sub(1);
+        components
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let extension = if i == last { extension } else { "" };
+                self.component(name, extension)
+            })
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    fn component(&self, name: &str, extension: &str) -> String {
+        let suffix = format!(".{extension}");
+        let (stem, suffix) = match name.strip_suffix(&suffix) {
+            Some(stem) if !extension.is_empty() => (stem, suffix.as_str()),
+            _ => (name, ""),
+        };
+
+        let stem = truncate(
+            stem.trim(),
+            self.max_component_length.saturating_sub(suffix.len()),
+        )
+        .trim_end();
+        // Windows drops trailing dots, leaving a name that no longer matches
+        let mut stem = match self.style {
+            PathStyle::Posix => stem.to_owned(),
+            PathStyle::Windows | PathStyle::Ascii => stem.trim_end_matches(['.', ' ']).to_owned(),
+        };
+        if self.style != PathStyle::Posix
+            && WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            stem.push('_');
+        }
+        if stem.is_empty() {
+            return String::new();
+        }
+        stem + suffix
+    }
+}
+
+/// Cut a string down to at most `max_bytes`, without splitting a character
+fn truncate(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitise_value() {
+        let posix = Sanitiser::default();
+        let windows = Sanitiser::new(PathStyle::Windows, 255);
+        let ascii = Sanitiser::new(PathStyle::Ascii, 255);
+        assert_eq!(posix.value("AC/DC: The Story"), "AC-DC: The Story");
+        assert_eq!(windows.value("AC/DC: The Story"), "AC-DC - The Story");
+        assert_eq!(
+            windows.value("Who Goes There?: A Novella"),
+            "Who Goes There - A Novella"
+        );
+        assert_eq!(ascii.value("Лев Толстой"), "Lev Tolstoi");
+        assert_eq!(ascii.value("Brontë"), "Bronte");
+    }
+
+    #[test]
+    fn test_sanitise_path() {
+        let windows = Sanitiser::new(PathStyle::Windows, 20);
+        assert_eq!(
+            windows.path("/Author. /con/A Very Long Title Indeed.epub", "epub"),
+            PathBuf::from("Author/con_/A Very Long Tit.epub")
+        );
+        assert_eq!(
+            Sanitiser::default().path("J.R.R. /a//../b.epub", "epub"),
+            PathBuf::from("J.R.R./a/b.epub")
+        );
+    }
+}
@@ -1,70 +1,446 @@
-use crate::parsers::parse_file;
-use crate::prompt::prompt_bool;
-use formatx::formatx;
+use crate::aliases::AuthorAliases;
+use crate::collision::{CollisionPolicy, Resolution, resolve};
+use crate::duplicates::{DuplicatePolicy, LibraryIndex};
+use crate::fileops::{missing_folders, remove_all, transfer};
+use crate::hash::same_contents;
+use crate::journal::Journal;
+use crate::parsers::audiobook_folder::is_audiobook_folder;
+use crate::parsers::{
+    AUDIOBOOK_EXTENSIONS, COMIC_EXTENSIONS, EBOOK_EXTENSIONS, FileMetadata, file_extension,
+    parse_file,
+};
+use crate::plan::{Operation, Plan, PlanEntry, PlanStatus};
+use crate::prompt::Interactor;
+use crate::review::{ReviewEntry, ReviewQueue};
+use crate::routing::{Rule, find_rule};
+use crate::sanitise::Sanitiser;
+use crate::template::{Template, TemplateError};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::read_dir;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// A folder that files are sorted into, and the template used to lay it out
+#[derive(Clone, Copy)]
+pub struct Library<'a> {
+    pub root: &'a Path,
+    pub template: &'a str,
+}
+
+/// The libraries that each kind of file is sorted into
+pub struct Libraries<'a> {
+    pub ebooks: Library<'a>,
+    pub audiobooks: Library<'a>,
+    pub comics: Library<'a>,
+}
+
+/// The variables a format template can use
+pub fn template_variables() -> Vec<&'static str> {
+    let mut variables: Vec<&str> = FileMetadata::default()
+        .template_values()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    variables.push("ext");
+    variables
+}
+
+/// Parse a format template, checking it only uses variables the sorter provides
+pub fn parse_template(pattern: &str) -> Result<Template, TemplateError> {
+    Template::parse_path(pattern, &template_variables())
+}
+
+/// When a move or copy goes ahead without asking "OK?" first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovePolicy {
+    /// Ask about every file
+    #[default]
+    Ask,
+    /// Never ask
+    Always,
+    /// Only ask about files that needed a prompt, or that collide with or duplicate a file
+    /// already in the library
+    WhenParsed,
+    /// Ask once for each folder of files
+    PerDirectory,
+}
 
 pub struct Sorter<'a> {
-    sort_pattern: &'a str,
     copy: bool,
+    interactor: &'a dyn Interactor,
+    aliases: Option<&'a AuthorAliases>,
+    journal: Option<&'a Journal>,
+    review_queue: Option<&'a ReviewQueue>,
+    sanitiser: Sanitiser,
+    extension_templates: Option<&'a BTreeMap<String, String>>,
+    rules: &'a [Rule],
+    duplicates: DuplicatePolicy,
+    collisions: CollisionPolicy,
+    approve: ApprovePolicy,
+    /// Set once the user answers "yes to all"
+    approve_all: Cell<bool>,
+    /// Set once the user asks to quit, so the remaining files are left alone
+    quit: Cell<bool>,
+    /// The answer for each folder, when asking once per folder
+    folder_answers: RefCell<HashMap<PathBuf, bool>>,
+    /// What is already in each library, built the first time a file is sorted into it
+    indexes: RefCell<HashMap<PathBuf, LibraryIndex>>,
+}
+
+/// What to do about a file that is already somewhere in the library
+enum Duplicate {
+    None,
+    Skip,
+    KeepBoth,
+    Replace(PathBuf),
 }
 
 impl<'a> Sorter<'a> {
-    pub fn new(sort_pattern: &'a str, copy: bool) -> Self {
-        Sorter { sort_pattern, copy }
+    pub fn new(copy: bool, interactor: &'a dyn Interactor) -> Self {
+        Sorter {
+            copy,
+            interactor,
+            aliases: None,
+            journal: None,
+            review_queue: None,
+            sanitiser: Sanitiser::default(),
+            extension_templates: None,
+            rules: &[],
+            duplicates: DuplicatePolicy::default(),
+            collisions: CollisionPolicy::default(),
+            approve: ApprovePolicy::default(),
+            approve_all: Cell::new(false),
+            quit: Cell::new(false),
+            folder_answers: RefCell::new(HashMap::new()),
+            indexes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Remember the authors picked for ambiguous files, and use the ones picked before
+    pub fn with_author_aliases(mut self, aliases: &'a AuthorAliases) -> Self {
+        self.aliases = Some(aliases);
+        self
+    }
+
+    /// Read a file's metadata, with the author aliases if there are any
+    fn parse(&self, file_path: &Path) -> Option<FileMetadata> {
+        match self.aliases {
+            Some(aliases) => parse_file(file_path, self.interactor, aliases),
+            None => parse_file(file_path, self.interactor, &AuthorAliases::default()),
+        }
+    }
+
+    /// Record every file operation in the given journal
+    pub fn with_journal(mut self, journal: &'a Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Queue files whose questions were put off, so they can be reviewed later
+    pub fn with_review_queue(mut self, review_queue: &'a ReviewQueue) -> Self {
+        self.review_queue = Some(review_queue);
+        self
+    }
+
+    /// Clean metadata with the given rules before it is used in a path
+    pub fn with_sanitiser(mut self, sanitiser: Sanitiser) -> Self {
+        self.sanitiser = sanitiser;
+        self
+    }
+
+    /// Use a different template for some extensions, keyed by lower case extension
+    pub fn with_extension_templates(mut self, templates: &'a BTreeMap<String, String>) -> Self {
+        self.extension_templates = Some(templates);
+        self
+    }
+
+    /// Send files matching these rules to their own libraries, the first match wins
+    pub fn with_rules(mut self, rules: &'a [Rule]) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// What to do when a file is already somewhere in the library
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// What to do when a file's destination already exists
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collisions = policy;
+        self
     }
 
-    pub fn sort_recursively(
+    /// When to go ahead without asking for confirmation
+    pub fn with_approve_policy(mut self, policy: ApprovePolicy) -> Self {
+        self.approve = policy;
+        self
+    }
+
+    /// Whether the user asked to quit part way through
+    pub fn stopped(&self) -> bool {
+        self.quit.get()
+    }
+
+    /// The library a file goes to: the first matching rule, or the default for its kind. The
+    /// rule's own template comes first, then the template for the file's extension, then the
+    /// library's.
+    fn route(
+        &self,
+        file_path: &Path,
+        source_root: &Path,
+        file_metadata: &FileMetadata,
+        default: &Library<'a>,
+    ) -> Library<'a> {
+        let rule = find_rule(self.rules, file_path, source_root, file_metadata);
+        let extension_template = || {
+            let ext = file_extension(file_path).filter(|_| !file_path.is_dir())?;
+            self.extension_templates?.get(&ext).map(String::as_str)
+        };
+        Library {
+            root: rule.map_or(default.root, |rule| Path::new(&rule.library)),
+            template: rule
+                .and_then(|rule| rule.template.as_deref())
+                .or_else(extension_template)
+                .unwrap_or(default.template),
+        }
+    }
+
+    fn operation(&self) -> Operation {
+        if self.copy {
+            Operation::Copy
+        } else {
+            Operation::Move
+        }
+    }
+
+    /// Walk the folder tree, calling `visit` with each supported file and the library it belongs in
+    fn for_each_book(
         &self,
         folder: &Path,
-        library_root_folder: &Path,
-        audiobook_root_folder: &Path,
+        libraries: &Libraries<'a>,
+        visit: &mut dyn FnMut(&Path, &Library<'a>),
     ) {
         if folder.is_dir() {
             if let Ok(dir_entries) = read_dir(folder) {
                 for entry in dir_entries.flatten() {
-                    self.sort_recursively(
-                        &entry.path(),
-                        library_root_folder,
-                        audiobook_root_folder,
-                    );
+                    let path = entry.path();
+                    // A folder of chapter files is sorted as one audiobook, never the source folder itself
+                    if is_audiobook_folder(&path) {
+                        visit(&path, &libraries.audiobooks);
+                    } else {
+                        self.for_each_book(&path, libraries, visit);
+                    }
                 }
             }
-        } else if let Some(ext) = folder.extension() {
-            if ext == "epub" || ext == "mobi" || ext == "m4b" || ext == "m4a" {
-                // Different base folder for audiobooks and regular books
-                let base_folder = if ext == "m4b" || ext == "m4a" {
-                    audiobook_root_folder
-                } else {
-                    library_root_folder
-                };
-                self.sort(folder, base_folder);
+        } else if let Some(ext) = file_extension(folder) {
+            // Different base folder for audiobooks, comics and regular books
+            let ext = ext.as_str();
+            if AUDIOBOOK_EXTENSIONS.contains(&ext) {
+                visit(folder, &libraries.audiobooks);
+            } else if COMIC_EXTENSIONS.contains(&ext) {
+                visit(folder, &libraries.comics);
+            } else if EBOOK_EXTENSIONS.contains(&ext)
+                || self.rules.iter().any(|rule| rule.names_extension(ext))
+            {
+                visit(folder, &libraries.ebooks);
             }
         }
     }
+
+    pub fn sort_recursively(&self, folder: &Path, libraries: &Libraries<'a>) {
+        self.for_each_book(folder, libraries, &mut |file_path, library| {
+            if !self.stopped() {
+                self.sort(file_path, folder, library);
+            }
+        });
+    }
+
+    /// Work out what `sort_recursively` would do, without touching any files
+    pub fn plan_recursively(&self, folder: &Path, libraries: &Libraries<'a>) -> Plan {
+        let mut plan = Plan::default();
+        self.for_each_book(folder, libraries, &mut |file_path, library| {
+            let mut entry = self.plan_file(file_path, folder, library);
+            // Renaming also has to step around destinations claimed earlier in the plan
+            if self.collisions == CollisionPolicy::Rename
+                && entry.status == PlanStatus::Pending
+                && let Some(destination) = &entry.destination
+                && plan.claims(destination)
+            {
+                entry.destination = Some(crate::collision::numbered_path(destination, &|p| {
+                    plan.claims(p)
+                }));
+            }
+            plan.push(entry)
+        });
+        plan
+    }
+
     fn check_target_file_is_same(&self, file_path: &Path, new_file_path: &Path) -> bool {
-        if new_file_path.exists() {
-            match (
-                std::fs::metadata(file_path),
-                std::fs::metadata(new_file_path),
-            ) {
-                (Ok(source_meta), Ok(dest_meta)) => {
-                    // Compare file sizes to see if they're likely the same file
-                    if source_meta.len() == dest_meta.len() {
-                        return true;
-                    }
+        file_path.is_file() && same_contents(file_path, new_file_path)
+    }
+
+    /// Look for a file with the same contents anywhere in the library, other than the file
+    /// itself or the destination it would be sorted to
+    fn find_duplicate(
+        &self,
+        file_path: &Path,
+        new_file_path: &Path,
+        library: &Library,
+    ) -> Option<PathBuf> {
+        if !file_path.is_file() {
+            return None;
+        }
+        let mut indexes = self.indexes.borrow_mut();
+        indexes
+            .entry(library.root.to_path_buf())
+            .or_insert_with(|| LibraryIndex::build(library.root))
+            .find_duplicate(file_path, &[file_path, new_file_path])
+    }
+
+    fn check_duplicate(
+        &self,
+        file_path: &Path,
+        new_file_path: &Path,
+        library: &Library,
+    ) -> Duplicate {
+        let Some(existing) = self.find_duplicate(file_path, new_file_path, library) else {
+            return Duplicate::None;
+        };
+        let policy = match self.duplicates {
+            DuplicatePolicy::Ask => {
+                let question = format!(
+                    "{} is already in the library as {}.",
+                    file_path.display(),
+                    existing.display()
+                );
+                match self.interactor.choose(
+                    &question,
+                    &["[s]kip it", "[r]eplace the existing file", "[k]eep both"],
+                ) {
+                    Some(1) => DuplicatePolicy::Replace,
+                    Some(2) => DuplicatePolicy::KeepBoth,
+                    _ => DuplicatePolicy::Skip,
                 }
-                _ => {
-                    // Can't get metadata for some reason, continue with the copy
+            }
+            policy => policy,
+        };
+        match policy {
+            DuplicatePolicy::Skip | DuplicatePolicy::Ask => Duplicate::Skip,
+            DuplicatePolicy::KeepBoth => Duplicate::KeepBoth,
+            DuplicatePolicy::Replace => Duplicate::Replace(existing),
+        }
+    }
+
+    /// Take a file out of the library, returning it if it went. With a journal the file is
+    /// set aside rather than deleted, so that undo can put it back.
+    fn remove_from_library(&self, path: &Path) -> Option<PathBuf> {
+        let result = match self.journal {
+            Some(journal) => journal.set_aside(path).map(|_| ()),
+            None => remove_all(path),
+        };
+        match result {
+            Ok(()) => {
+                println!("Removed {}.", path.display());
+                Some(path.to_path_buf())
+            }
+            Err(e) => {
+                eprintln!("Failed to remove {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Keep the library index up to date after a file has been sorted into it
+    fn update_index(
+        &self,
+        library: &Library,
+        file_path: &Path,
+        new_file_path: &Path,
+        replaced: Option<&Path>,
+    ) {
+        if let Some(index) = self.indexes.borrow_mut().get_mut(library.root) {
+            // A file being re-sorted within the library is no longer where it was
+            if !self.copy {
+                index.remove(file_path);
+            }
+            if let Some(replaced) = replaced {
+                index.remove(replaced);
+            }
+            index.insert(new_file_path);
+        }
+    }
+
+    /// Ask which collision policy to use for this file, skipping it if there is no answer
+    fn ask_collision_policy(&self, new_file_path: &Path) -> CollisionPolicy {
+        let question = format!("{} already exists.", new_file_path.display());
+        let choices = [
+            "[s]kip",
+            "[o]verwrite",
+            "[r]ename",
+            "keep [l]arger",
+            "keep [n]ewer",
+        ];
+        match self.interactor.choose(&question, &choices) {
+            Some(1) => CollisionPolicy::Overwrite,
+            Some(2) => CollisionPolicy::Rename,
+            Some(3) => CollisionPolicy::KeepLarger,
+            Some(4) => CollisionPolicy::KeepNewer,
+            _ => CollisionPolicy::Skip,
+        }
+    }
+
+    /// Whether the move or copy described by `description` can go ahead, asking if the approve
+    /// policy says to. `unsure` is set when a prompt, collision or duplicate was involved.
+    fn approved(&self, file_path: &Path, description: &str, unsure: bool) -> bool {
+        // Nobody is there to answer in a non-interactive run
+        if !self.interactor.is_interactive() || self.approve_all.get() {
+            return true;
+        }
+        let folder = file_path.parent().unwrap_or(file_path).to_path_buf();
+        match self.approve {
+            ApprovePolicy::Always => return true,
+            ApprovePolicy::WhenParsed if !unsure => return true,
+            ApprovePolicy::PerDirectory => {
+                if let Some(&answer) = self.folder_answers.borrow().get(&folder) {
+                    return answer;
                 }
             }
+            ApprovePolicy::Ask | ApprovePolicy::WhenParsed => {}
+        }
+        println!("{description}");
+        let prompt_text = if self.approve == ApprovePolicy::PerDirectory {
+            format!("OK for every file in {}?", folder.display())
+        } else {
+            String::from("OK?")
+        };
+        let choices = ["[y]es", "[n]o", "yes to [a]ll", "[q]uit"];
+        let answer = match self.interactor.choose(&prompt_text, &choices) {
+            Some(0) => true,
+            Some(2) => {
+                self.approve_all.set(true);
+                true
+            }
+            Some(3) => {
+                self.quit.set(true);
+                return false;
+            }
+            _ => false,
+        };
+        if self.approve == ApprovePolicy::PerDirectory {
+            self.folder_answers.borrow_mut().insert(folder, answer);
         }
-        false
+        answer
     }
-    fn act_on_file(&self, file_path: &Path, new_file_path: &Path) {
+
+    /// Move or copy the file once it is approved, returning where it ended up
+    fn act_on_file(&self, file_path: &Path, new_file_path: &Path, unsure: bool) -> Option<PathBuf> {
         if new_file_path == file_path {
             println!("File {} already has the correct name.", file_path.display());
-            return;
+            return None;
         }
         if self.copy && self.check_target_file_is_same(file_path, new_file_path) {
             // If we're copying and the target file is the same, skip
@@ -73,82 +449,466 @@ impl<'a> Sorter<'a> {
                 file_path.display(),
                 new_file_path.display()
             );
-            return;
-        }
-        let action = if self.copy { "copy" } else { "move" };
-        println!("Want to {action} file from {file_path:?} to {new_file_path:?}");
-        // Ask user to move
-        if !prompt_bool("OK?") {
-            return;
-        }
-        // Need to make folder path if it doesn't exist
-        if let Some(parent) = new_file_path.parent() {
-            if !parent.exists() {
-                if let Err(e) = std::fs::create_dir_all(parent) {
-                    eprintln!("Failed to create directory {}: {}", parent.display(), e);
-                    return;
+            return None;
+        }
+        let mut new_file_path = new_file_path.to_path_buf();
+        let mut overwrite = false;
+        let collided = new_file_path.exists();
+        if collided {
+            let policy = match self.collisions {
+                CollisionPolicy::Ask => self.ask_collision_policy(&new_file_path),
+                policy => policy,
+            };
+            match resolve(policy, file_path, &new_file_path, &|_| false) {
+                Resolution::Skip(reason) => {
+                    println!("Skipping {}: {reason}.", file_path.display());
+                    return None;
                 }
+                Resolution::Overwrite => overwrite = true,
+                Resolution::Rename(renamed) => new_file_path = renamed,
             }
         }
+        let action = self.operation();
+        let mut description =
+            format!("Want to {action} file from {file_path:?} to {new_file_path:?}");
+        if overwrite {
+            description.push_str(", replacing the existing file");
+        }
+        if !self.approved(file_path, &description, unsure || collided) {
+            return None;
+        }
+        if overwrite && self.remove_from_library(&new_file_path).is_none() {
+            return None;
+        }
+        let created = missing_folders(&new_file_path);
+        if !transfer(action, file_path, &new_file_path) {
+            return None;
+        }
+        if let Some(journal) = self.journal {
+            journal.record(action, file_path, &new_file_path, &created);
+        }
+        Some(new_file_path)
+    }
 
-        // Decide whether to copy or move the file based on the copy flag
-        if self.copy {
-            // Copy mode - copy file and leave original intact
-            // Check if the target file already exists, and is the same as the source if so, skip
-            // This is to prevent unnecessary copying
-            // Check if the destination file already exists
-
-            if std::fs::copy(file_path, new_file_path).is_ok() {
-                println!(
-                    "Copied {} to {}",
-                    file_path.display(),
-                    new_file_path.display()
-                );
-            } else {
-                eprintln!("Failed to copy file {}", file_path.display());
-            }
+    /// Work out what a plan would do about an existing destination, without asking
+    fn plan_collision(&self, file_path: &Path, new_file_path: PathBuf) -> (PlanStatus, PathBuf) {
+        // Asking is left to whoever reviews the plan
+        if self.collisions == CollisionPolicy::Ask {
+            return (PlanStatus::Collision, new_file_path);
+        }
+        match resolve(self.collisions, file_path, &new_file_path, &|_| false) {
+            Resolution::Skip(reason) => (PlanStatus::Skipped { reason }, new_file_path),
+            Resolution::Overwrite => (PlanStatus::Replace, new_file_path),
+            Resolution::Rename(renamed) => (PlanStatus::Pending, renamed),
+        }
+    }
+
+    fn destination_for(
+        &self,
+        file_path: &Path,
+        file_metadata: &FileMetadata,
+        library: &Library,
+    ) -> Result<PathBuf, String> {
+        // An audiobook folder keeps its own file names, so the template names the folder itself
+        let is_folder = file_path.is_dir();
+        // Given a file path and its metadata, we generate a new file name based on the sort pattern
+        let ext = if is_folder {
+            String::new()
         } else {
-            // Move/rename mode (default behavior)
-            if let Err(e) = std::fs::rename(file_path, new_file_path) {
-                // If rename fails, try copy + delete as fallback
-                if std::fs::copy(file_path, new_file_path).is_ok() {
-                    if let Err(e) = std::fs::remove_file(file_path) {
-                        eprintln!("Failed to remove original file: {e}");
-                    }
-                    println!(
-                        "Moved (via copy) {} to {}",
-                        file_path.display(),
-                        new_file_path.display()
-                    );
-                } else {
-                    eprintln!("Failed to copy file: {e}");
+            file_extension(file_path).ok_or("file has no extension")?
+        };
+        let template = parse_template(library.template)
+            .map_err(|e| format!("could not parse format template: {e}"))?;
+        // Values are cleaned one by one, so only the template's own "/" make folders
+        let mut values: Vec<(&str, String)> = file_metadata
+            .template_values()
+            .into_iter()
+            .map(|(name, value)| (name, self.sanitiser.value(&value)))
+            .collect();
+        values.push(("ext", self.sanitiser.value(&ext)));
+        let mut new_file_name = template
+            .render(&values)
+            .map_err(|e| format!("could not apply format template: {e}"))?;
+        if is_folder {
+            // "{title}.{ext}" leaves a dot behind when there is no extension
+            new_file_name = new_file_name.trim_end_matches('.').to_owned();
+        }
+        // Empty variables can leave a leading or doubled "/", which must not escape the library
+        Ok(library.root.join(self.sanitiser.path(&new_file_name, &ext)))
+    }
+
+    /// Work out where a file would be sorted to, and whether anything is in the way
+    pub fn plan_file(
+        &self,
+        file_path: &Path,
+        source_root: &Path,
+        library: &Library<'a>,
+    ) -> PlanEntry {
+        let operation = self.operation();
+        let file_metadata = self.parse(file_path);
+        // The answers to any questions put off while parsing are missing from the metadata
+        if let Some(question) = self.interactor.take_declined().first() {
+            let reason = format!("needs an answer to \"{}\"", question.prompt);
+            return PlanEntry::skipped(file_path, operation, &reason);
+        }
+        let Some(file_metadata) = file_metadata else {
+            return PlanEntry::skipped(file_path, operation, "could not read metadata");
+        };
+        let library = self.route(file_path, source_root, &file_metadata, library);
+        let new_file_path = match self.destination_for(file_path, &file_metadata, &library) {
+            Ok(new_file_path) => new_file_path,
+            Err(reason) => return PlanEntry::skipped(file_path, operation, &reason),
+        };
+        let (status, new_file_path) = if new_file_path == file_path {
+            (PlanStatus::InPlace, new_file_path)
+        } else if self.copy && self.check_target_file_is_same(file_path, &new_file_path) {
+            (PlanStatus::Identical, new_file_path)
+        } else if new_file_path.exists() {
+            self.plan_collision(file_path, new_file_path)
+        } else if let Some(existing) = self.find_duplicate(file_path, &new_file_path, &library) {
+            // Asking is left to whoever reviews the plan
+            let status = match self.duplicates {
+                DuplicatePolicy::Ask | DuplicatePolicy::Skip => PlanStatus::Duplicate { existing },
+                DuplicatePolicy::KeepBoth => PlanStatus::Pending,
+                DuplicatePolicy::Replace => PlanStatus::ReplaceDuplicate { existing },
+            };
+            (status, new_file_path)
+        } else {
+            (PlanStatus::Pending, new_file_path)
+        };
+        PlanEntry {
+            source: file_path.to_path_buf(),
+            destination: Some(new_file_path),
+            operation,
+            status,
+            metadata: Some(file_metadata),
+        }
+    }
+
+    /// Sort one file, queueing it for review if a question went unanswered. Returns true if
+    /// the file was moved or copied into the library.
+    pub fn sort(&self, file_path: &Path, source_root: &Path, library: &Library<'a>) -> bool {
+        let sorted = self.sort_file(file_path, source_root, library);
+        let questions = self.interactor.take_declined();
+        if questions.is_empty() {
+            return sorted;
+        }
+        println!(
+            "Skipping {}, it needs an answer to \"{}\".",
+            file_path.display(),
+            questions[0].prompt
+        );
+        if let Some(review_queue) = self.review_queue {
+            // The review may well be run from another folder
+            let absolute = |path: &Path| std::path::absolute(path).unwrap_or(path.to_path_buf());
+            review_queue.add(ReviewEntry {
+                source: absolute(file_path),
+                source_root: absolute(source_root),
+                library: library.root.to_path_buf(),
+                template: library.template.to_owned(),
+                questions,
+            });
+        }
+        sorted
+    }
+
+    fn sort_file(&self, file_path: &Path, source_root: &Path, library: &Library<'a>) -> bool {
+        // Given a file path, work out where it belongs and then move it to that path if its different from the original path.
+        let asked = self.interactor.questions_asked();
+        let Some(file_metadata) = self.parse(file_path) else {
+            return false;
+        };
+        // Without the answers the metadata is incomplete, so the file waits for review
+        if !self.interactor.is_interactive() && self.interactor.questions_asked() > asked {
+            return false;
+        }
+        let library = self.route(file_path, source_root, &file_metadata, library);
+        match self.destination_for(file_path, &file_metadata, &library) {
+            Ok(new_file_path) => {
+                let duplicate = self.check_duplicate(file_path, &new_file_path, &library);
+                if matches!(duplicate, Duplicate::Skip) {
+                    println!("Skipping {}.", file_path.display());
+                    return false;
                 }
-            } else {
-                println!(
-                    "Moved {} to {}",
-                    file_path.display(),
-                    new_file_path.display()
-                );
+                let unsure = self.interactor.questions_asked() > asked
+                    || !matches!(duplicate, Duplicate::None);
+                let Some(new_file_path) = self.act_on_file(file_path, &new_file_path, unsure)
+                else {
+                    return false;
+                };
+                let replaced = match duplicate {
+                    Duplicate::Replace(existing) => self.remove_from_library(&existing),
+                    Duplicate::None | Duplicate::KeepBoth | Duplicate::Skip => None,
+                };
+                self.update_index(&library, file_path, &new_file_path, replaced.as_deref());
+                true
+            }
+            Err(reason) => {
+                eprintln!("Skipping {}: {reason}", file_path.display());
+                false
             }
         }
     }
-    pub fn sort(&self, file_path: &Path, library_root_folder: &Path) {
-        // Given a file path, we parse the file metadata, generate a new file name based on the sort pattern,
-        // and then move it to that path if its different from the original path.
+}
 
-        if let Some(file_metadata) = parse_file(file_path) {
-            //We have file metadata, so we can generate a new file name
-            if let Some(ext) = file_path.extension() {
-                if let Ok(new_file_name) = formatx!(
-                    self.sort_pattern,
-                    title = file_metadata.title,
-                    author = file_metadata.main_author,
-                    ext = ext.to_string_lossy(),
-                ) {
-                    let new_file_path = library_root_folder.join(new_file_name);
-                    self.act_on_file(file_path, &new_file_path);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::Scripted;
+    use std::fs;
+
+    fn temp_dir() -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ebook_organiser_sorter_test_{}",
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(path.join("in")).unwrap();
+        fs::create_dir_all(path.join("lib")).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_plan_duplicate_policy() -> std::io::Result<()> {
+        let root = temp_dir();
+        let existing = root.join("lib/Old/Book.txt");
+        let file = root.join("in/book.txt");
+        fs::create_dir_all(root.join("lib/Old"))?;
+        fs::write(&existing, "same")?;
+        fs::write(&file, "same")?;
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+
+        // There is no parser for .txt, so the title and author are asked for
+        let status = |policy| {
+            let scripted = Scripted::new(&["Book", "Author"]);
+            Sorter::new(false, &scripted)
+                .with_duplicate_policy(policy)
+                .plan_file(&file, &root.join("in"), &library)
+                .status
+        };
+        assert_eq!(
+            status(DuplicatePolicy::Ask),
+            PlanStatus::Duplicate {
+                existing: existing.clone()
             }
+        );
+        assert_eq!(status(DuplicatePolicy::KeepBoth), PlanStatus::Pending);
+        assert_eq!(
+            status(DuplicatePolicy::Replace),
+            PlanStatus::ReplaceDuplicate {
+                existing: existing.clone()
+            }
+        );
+
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_undo_overwrite() -> std::io::Result<()> {
+        let root = temp_dir();
+        let journal_path = root.join("journal.jsonl");
+        let existing = root.join("lib/Author/Book.txt");
+        let file = root.join("in/book.txt");
+        fs::create_dir_all(root.join("lib/Author"))?;
+        fs::write(&existing, "old")?;
+        fs::write(&file, "new")?;
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+
+        let journal = Journal::new(&journal_path);
+        let scripted = Scripted::new(&["Book", "Author", "y"]);
+        let sorter = Sorter::new(false, &scripted)
+            .with_journal(&journal)
+            .with_collision_policy(CollisionPolicy::Overwrite);
+        assert!(sorter.sort(&file, &root.join("in"), &library));
+        assert_eq!(fs::read_to_string(&existing)?, "new");
+
+        // The overwritten file was set aside, so undo brings it back
+        assert_eq!(crate::journal::undo(&journal_path, None)?, 2);
+        assert_eq!(fs::read_to_string(&existing)?, "old");
+        assert_eq!(fs::read_to_string(&file)?, "new");
+
+        fs::remove_dir_all(&root)
+    }
+
+    /// Write an MP3 that is just an ID3 tag, with no author if `author` is empty
+    fn write_mp3(path: &Path, title: &str, author: &str) {
+        use id3::TagLike;
+        let mut tag = id3::Tag::new();
+        tag.set_title(title);
+        if !author.is_empty() {
+            tag.set_artist(author);
+        }
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+    }
+
+    #[test]
+    fn test_yes_to_all_and_quit() -> std::io::Result<()> {
+        let root = temp_dir();
+        let source = root.join("in");
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+        let files: Vec<PathBuf> = ["a", "b", "c"]
+            .iter()
+            .map(|name| source.join(format!("{name}.mp3")))
+            .collect();
+        for file in &files {
+            write_mp3(file, &file.file_stem().unwrap().to_string_lossy(), "Author");
         }
+
+        // Yes to all is asked once, for the first file
+        let scripted = Scripted::new(&["a"]);
+        let sorter = Sorter::new(true, &scripted);
+        assert!(
+            files
+                .iter()
+                .all(|file| sorter.sort(file, &source, &library))
+        );
+        assert_eq!(scripted.questions_asked(), 1);
+        fs::remove_dir_all(root.join("lib"))?;
+
+        // Quitting leaves that file and the rest alone
+        let scripted = Scripted::new(&["y", "q"]);
+        let sorter = Sorter::new(true, &scripted);
+        assert!(sorter.sort(&files[0], &source, &library));
+        assert!(!sorter.sort(&files[1], &source, &library));
+        assert!(sorter.stopped());
+        assert_eq!(scripted.questions_asked(), 2);
+
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_approve_when_parsed() -> std::io::Result<()> {
+        let root = temp_dir();
+        let source = root.join("in");
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+        let tagged = source.join("tagged.mp3");
+        let untagged = source.join("untagged.mp3");
+        write_mp3(&tagged, "Tagged", "Author");
+        write_mp3(&untagged, "Untagged", "");
+
+        let scripted = Scripted::new(&["Author", "n"]);
+        let sorter = Sorter::new(false, &scripted).with_approve_policy(ApprovePolicy::WhenParsed);
+        // Fully tagged, so it goes without asking
+        assert!(sorter.sort(&tagged, &source, &library));
+        assert_eq!(scripted.questions_asked(), 0);
+        // The author had to be asked for, so the move is checked too
+        assert!(!sorter.sort(&untagged, &source, &library));
+        assert_eq!(
+            scripted.prompts.borrow()[1],
+            "OK? [y]es, [n]o, yes to [a]ll, [q]uit"
+        );
+        assert!(untagged.exists());
+
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_approve_per_directory() -> std::io::Result<()> {
+        let root = temp_dir();
+        let source = root.join("in");
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+        let files = [
+            source.join("one/a.mp3"),
+            source.join("one/b.mp3"),
+            source.join("two/c.mp3"),
+        ];
+        for file in &files {
+            write_mp3(file, &file.file_stem().unwrap().to_string_lossy(), "Author");
+        }
+
+        let scripted = Scripted::new(&["y", "n"]);
+        let sorter = Sorter::new(false, &scripted).with_approve_policy(ApprovePolicy::PerDirectory);
+        let sorted: Vec<bool> = files
+            .iter()
+            .map(|file| sorter.sort(file, &source, &library))
+            .collect();
+        assert_eq!(sorted, vec![true, true, false]);
+        // One question for each folder
+        let prompts = scripted.prompts.borrow();
+        assert_eq!(prompts.len(), 2);
+        assert!(prompts[0].starts_with(&format!(
+            "OK for every file in {}?",
+            source.join("one").display()
+        )));
+
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_sort_reports_whether_file_moved() -> std::io::Result<()> {
+        let root = temp_dir();
+        let file = root.join("in/book.txt");
+        fs::write(&file, "book")?;
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+
+        let declined = Scripted::new(&["Book", "Author", "n"]);
+        assert!(!Sorter::new(false, &declined).sort(&file, &root.join("in"), &library));
+        assert!(file.exists());
+        let approved = Scripted::new(&["Book", "Author", "y"]);
+        assert!(Sorter::new(false, &approved).sort(&file, &root.join("in"), &library));
+        assert!(root.join("lib/Author/Book.txt").exists());
+
+        fs::remove_dir_all(&root)
+    }
+
+    #[test]
+    fn test_rule_template_comes_first() -> std::io::Result<()> {
+        let root = temp_dir();
+        let file = root.join("in/book.txt");
+        fs::write(&file, "book")?;
+        let library = Library {
+            root: &root.join("lib"),
+            template: "{author}/{title}.{ext}",
+        };
+        let extension_templates =
+            BTreeMap::from([(String::from("txt"), String::from("Text/{title}.{ext}"))]);
+        let rules = [Rule {
+            extensions: vec![String::from("txt")],
+            library: root.join("routed").to_string_lossy().into_owned(),
+            template: Some(String::from("Routed/{title}.{ext}")),
+            ..Default::default()
+        }];
+
+        let destination = |rules: &[Rule]| {
+            let scripted = Scripted::new(&["Book", "Author"]);
+            Sorter::new(false, &scripted)
+                .with_extension_templates(&extension_templates)
+                .with_rules(rules)
+                .plan_file(&file, &root.join("in"), &library)
+                .destination
+        };
+        assert_eq!(
+            destination(&rules),
+            Some(root.join("routed/Routed/Book.txt"))
+        );
+        // Without a rule template the extension's is used, in the rule's library
+        let mut untemplated = rules.clone();
+        untemplated[0].template = None;
+        assert_eq!(
+            destination(&untemplated),
+            Some(root.join("routed/Text/Book.txt"))
+        );
+        assert_eq!(destination(&[]), Some(root.join("lib/Text/Book.txt")));
+
+        fs::remove_dir_all(&root)
     }
 }
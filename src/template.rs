@@ -0,0 +1,435 @@
+use crate::names::sort_name;
+use std::fmt;
+
+/// A parsed `format_template`.
+///
+/// Variables are written `{title}`, optionally followed by filters: `{author|sortname|initial}`.
+/// A section such as `{series?}{series}/{/series}` is only kept when its variable is not empty.
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Variable {
+        name: String,
+        filters: Vec<Filter>,
+        position: usize,
+    },
+    Section {
+        name: String,
+        children: Vec<Node>,
+        position: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    Lower,
+    Upper,
+    /// First letter, for A-Z bucket folders
+    Initial,
+    Truncate(usize),
+    /// Zero pad the leading number, for series indices
+    Pad(usize),
+    /// "J.R.R. Tolkien" becomes "Tolkien, J.R.R."
+    SortName,
+    /// Used in place of an empty value
+    Default(String),
+}
+
+/// A problem with a template, and the byte offset in the template where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+fn error(position: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError {
+        position,
+        message: message.into(),
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_filter(filter: &str, position: usize) -> Result<Filter, TemplateError> {
+    let (name, argument) = match filter.split_once('(') {
+        Some((name, rest)) => {
+            let argument = rest
+                .strip_suffix(')')
+                .ok_or_else(|| error(position, format!("missing ')' in filter '{filter}'")))?;
+            (name.trim(), Some(argument))
+        }
+        None => (filter.trim(), None),
+    };
+    let number = || {
+        argument
+            .and_then(|a| a.trim().parse::<usize>().ok())
+            .ok_or_else(|| {
+                error(
+                    position,
+                    format!("filter '{name}' needs a number, e.g. {name}(2)"),
+                )
+            })
+    };
+    match name {
+        "lower" => Ok(Filter::Lower),
+        "upper" => Ok(Filter::Upper),
+        "initial" => Ok(Filter::Initial),
+        "sortname" => Ok(Filter::SortName),
+        "truncate" => Ok(Filter::Truncate(number()?)),
+        "pad" => Ok(Filter::Pad(number()?)),
+        "default" => argument
+            .map(|a| Filter::Default(a.to_owned()))
+            .ok_or_else(|| {
+                error(
+                    position,
+                    "filter 'default' needs a value, e.g. default(Unknown)",
+                )
+            }),
+        _ => Err(error(position, format!("unknown filter '{name}'"))),
+    }
+}
+
+/// Parse a `{...}` tag starting at `position`, not counting its braces
+fn parse_tag(tag: &str, position: usize) -> Result<Node, TemplateError> {
+    if let Some(name) = tag.strip_suffix('?') {
+        let name = name.trim();
+        if !is_valid_name(name) {
+            return Err(error(position, format!("invalid variable name '{name}'")));
+        }
+        return Ok(Node::Section {
+            name: name.to_owned(),
+            children: Vec::new(),
+            position,
+        });
+    }
+    let mut parts = tag.split('|');
+    let name = parts.next().unwrap_or_default().trim();
+    if !is_valid_name(name) {
+        return Err(error(position, format!("invalid variable name '{name}'")));
+    }
+    let filters = parts
+        .map(|filter| parse_filter(filter, position))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Node::Variable {
+        name: name.to_owned(),
+        filters,
+        position,
+    })
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        // Sections still open, with the nodes collected so far outside of them
+        let mut stack: Vec<(Node, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.char_indices().peekable();
+
+        while let Some((position, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|&(_, c)| c == '{').is_some() => text.push('{'),
+                '}' if chars.next_if(|&(_, c)| c == '}').is_some() => text.push('}'),
+                '}' => return Err(error(position, "unmatched '}'")),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((inner, '{')) => {
+                                return Err(error(inner, "'{' inside a variable"));
+                            }
+                            Some((_, c)) => tag.push(c),
+                            None => return Err(error(position, "unclosed '{'")),
+                        }
+                    }
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+
+                    if let Some(closing) = tag.strip_prefix('/') {
+                        let Some((
+                            Node::Section {
+                                name,
+                                position: opened,
+                                ..
+                            },
+                            outer,
+                        )) = stack.pop()
+                        else {
+                            return Err(error(
+                                position,
+                                format!("'{{/{closing}}}' closes no section"),
+                            ));
+                        };
+                        if name != closing.trim() {
+                            return Err(error(
+                                position,
+                                format!(
+                                    "'{{/{closing}}}' does not match '{{{name}?}}' at position {opened}"
+                                ),
+                            ));
+                        }
+                        let children = std::mem::replace(&mut nodes, outer);
+                        nodes.push(Node::Section {
+                            name,
+                            children,
+                            position: opened,
+                        });
+                    } else {
+                        match parse_tag(&tag, position)? {
+                            section @ Node::Section { .. } => {
+                                stack.push((section, std::mem::take(&mut nodes)));
+                            }
+                            node => nodes.push(node),
+                        }
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if let Some((Node::Section { name, position, .. }, _)) = stack.pop() {
+            return Err(error(
+                position,
+                format!("'{{{name}?}}' is never closed with '{{/{name}}}'"),
+            ));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template { nodes })
+    }
+
+    /// Parse a template for a path inside a library, checking that it only uses `variables`
+    /// and can't point outside of the library
+    pub fn parse_path(source: &str, variables: &[&str]) -> Result<Template, TemplateError> {
+        let is_drive = source.len() >= 2
+            && source.as_bytes()[0].is_ascii_alphabetic()
+            && source.as_bytes()[1] == b':';
+        if source.starts_with(['/', '\\']) || is_drive {
+            return Err(error(0, "template must be a relative path"));
+        }
+        let mut offset = 0;
+        for component in source.split(['/', '\\']) {
+            if component.trim() == ".." {
+                return Err(error(offset, "'..' would lead outside of the library"));
+            }
+            offset += component.len() + 1;
+        }
+
+        let template = Template::parse(source)?;
+        for (name, position) in template.variables() {
+            if !variables.contains(&name) {
+                return Err(error(position, format!("unknown variable '{{{name}}}'")));
+            }
+        }
+        Ok(template)
+    }
+
+    /// Every variable used by the template, with its position
+    pub fn variables(&self) -> Vec<(&str, usize)> {
+        fn collect<'a>(nodes: &'a [Node], found: &mut Vec<(&'a str, usize)>) {
+            for node in nodes {
+                match node {
+                    Node::Text(_) => {}
+                    Node::Variable { name, position, .. } => found.push((name, *position)),
+                    Node::Section {
+                        name,
+                        children,
+                        position,
+                    } => {
+                        found.push((name, *position));
+                        collect(children, found);
+                    }
+                }
+            }
+        }
+        let mut found = Vec::new();
+        collect(&self.nodes, &mut found);
+        found
+    }
+
+    /// Fill in the template, failing on any variable that is not in `values`
+    pub fn render(&self, values: &[(&str, String)]) -> Result<String, TemplateError> {
+        let mut output = String::new();
+        render_nodes(&self.nodes, values, &mut output)?;
+        Ok(output)
+    }
+}
+
+fn lookup<'a>(
+    values: &'a [(&str, String)],
+    name: &str,
+    position: usize,
+) -> Result<&'a str, TemplateError> {
+    values
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value.as_str())
+        .ok_or_else(|| error(position, format!("unknown variable '{{{name}}}'")))
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    values: &[(&str, String)],
+    output: &mut String,
+) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable {
+                name,
+                filters,
+                position,
+            } => {
+                let value = lookup(values, name, *position)?;
+                let value = filters
+                    .iter()
+                    .fold(value.to_owned(), |value, filter| apply(filter, &value));
+                output.push_str(&value);
+            }
+            Node::Section {
+                name,
+                children,
+                position,
+            } => {
+                if !lookup(values, name, *position)?.trim().is_empty() {
+                    render_nodes(children, values, output)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply(filter: &Filter, value: &str) -> String {
+    match filter {
+        Filter::Lower => value.to_lowercase(),
+        Filter::Upper => value.to_uppercase(),
+        Filter::Initial => match value.chars().find(|c| c.is_alphanumeric()) {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+            Some(_) => String::from("#"),
+            None => String::new(),
+        },
+        Filter::Truncate(length) => value
+            .chars()
+            .take(*length)
+            .collect::<String>()
+            .trim_end()
+            .to_owned(),
+        Filter::Pad(width) => {
+            let digits = value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(value.len());
+            if digits == 0 {
+                value.to_owned()
+            } else {
+                format!("{:0>width$}{}", &value[..digits], &value[digits..])
+            }
+        }
+        Filter::SortName => sort_name(value),
+        Filter::Default(default) if value.trim().is_empty() => default.clone(),
+        Filter::Default(_) => value.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> Vec<(&'static str, String)> {
+        vec![
+            ("title", String::from("The Hobbit")),
+            ("author", String::from("J.R.R. Tolkien")),
+            ("series", String::new()),
+            ("series_index", String::from("1.5")),
+            ("ext", String::from("epub")),
+        ]
+    }
+
+    fn render(template: &str) -> String {
+        Template::parse(template)
+            .unwrap()
+            .render(&values())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_plain_template() {
+        assert_eq!(
+            render("{author}/{title}.{ext}"),
+            "J.R.R. Tolkien/The Hobbit.epub"
+        );
+        assert_eq!(render("{{{title}}}"), "{The Hobbit}");
+    }
+
+    #[test]
+    fn test_sections_and_defaults() {
+        assert_eq!(
+            render("{author}/{series?}{series}/{series_index|pad(2)} - {/series}{title}.{ext}"),
+            "J.R.R. Tolkien/The Hobbit.epub"
+        );
+        assert_eq!(
+            render("{title?}[{series|default(Standalone)}] {/title}{title}"),
+            "[Standalone] The Hobbit"
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            render("{author|initial}/{author|sortname}"),
+            "J/Tolkien, J.R.R."
+        );
+        assert_eq!(render("{title|upper|truncate(7)}"), "THE HOB");
+        assert_eq!(render("{series_index|pad(3)}"), "001.5");
+        assert_eq!(render("{title|lower}"), "the hobbit");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |template: &str| Template::parse(template).unwrap_err().position;
+        assert_eq!(error("{author/{title}"), 8);
+        assert_eq!(error("{author}}"), 8);
+        assert_eq!(error("{series?}{series}"), 0);
+        assert_eq!(error("{title|shout}"), 0);
+
+        let unknown = Template::parse("{author}/{autor}")
+            .unwrap()
+            .render(&values())
+            .unwrap_err();
+        assert_eq!(
+            unknown.to_string(),
+            "unknown variable '{autor}' at position 9"
+        );
+    }
+
+    #[test]
+    fn test_parse_path() {
+        let variables = ["title", "author", "ext"];
+        let error = |template: &str| Template::parse_path(template, &variables).unwrap_err();
+        assert!(Template::parse_path("{author}/{title}.{ext}", &variables).is_ok());
+        assert_eq!(error("{author}/{autor}.{ext}").position, 9);
+        assert_eq!(error("{author}/{title?}{/title}{series}").position, 25);
+        assert_eq!(error("/books/{title}").position, 0);
+        assert_eq!(error("C:\\books\\{title}").position, 0);
+        assert_eq!(error("{author}/../{title}").position, 9);
+    }
+}